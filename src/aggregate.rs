@@ -0,0 +1,560 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error, Result};
+
+use crate::capture::Direction;
+use crate::collector::{fmt_dimensions, parse_dimensions, ConnectionKey, Dimension, Flow, KeySpec};
+use crate::time::rfc3339;
+
+pub const CSV_HEADER: &str = "window_start,window_end,source,port,count";
+
+/// how digests are written to stdout and files
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DigestFormat {
+    /// the sections, as `group:total item:count` lines
+    #[default]
+    Text,
+    /// a row per inbound source and port, after a header line
+    Csv,
+}
+
+impl FromStr for DigestFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(DigestFormat::Text),
+            "csv" => Ok(DigestFormat::Csv),
+            other => Err(anyhow!("unknown format: {other} (expected text or csv)")),
+        }
+    }
+}
+
+/// quotes a field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// ordered list of dimensions used to group collected records.
+/// the first dimension names each row, the remaining dimensions are counted within the row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupBy(Vec<Dimension>);
+
+impl GroupBy {
+    pub fn dimensions(&self) -> &[Dimension] {
+        &self.0
+    }
+
+    /// checks that every dimension was retained by the collector
+    pub fn validate(&self, key: &KeySpec) -> Result<()> {
+        match self.0.iter().find(|&&d| !key.contains(d)) {
+            Some(missing) => Err(anyhow!(
+                "cannot group by {missing}: not counted by key {key}"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// sums the counts of records which share the same values for these dimensions
+    pub fn aggregate<'a, I>(&self, records: I) -> Result<Table>
+    where
+        I: IntoIterator<Item = (&'a ConnectionKey, &'a u64)>,
+    {
+        let (group_dimension, item_dimensions) =
+            self.0.split_first().ok_or(anyhow!("empty grouping"))?;
+        let label = |dimension: &Dimension, key: &ConnectionKey| {
+            dimension
+                .label(key)
+                .ok_or(anyhow!("missing {dimension} value"))
+        };
+
+        let mut grouped: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        for (key, count) in records {
+            let item = item_dimensions
+                .iter()
+                .map(|d| label(d, key))
+                .collect::<Result<Vec<String>>>()?
+                .join("/");
+            *grouped
+                .entry(label(group_dimension, key)?)
+                .or_default()
+                .entry(item)
+                .or_default() += count;
+        }
+
+        Ok(Table::from_grouped(grouped))
+    }
+}
+
+impl From<&KeySpec> for GroupBy {
+    fn from(key: &KeySpec) -> Self {
+        GroupBy(key.dimensions().to_vec())
+    }
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_dimensions(&self.0, f)
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(GroupBy(parse_dimensions(s)?))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub group: String,
+    pub total: u64,
+    /// empty when grouping by a single dimension
+    pub items: Vec<(String, u64)>,
+}
+
+/// rows sorted by total descending, items within each row sorted by count descending
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Table {
+    pub rows: Vec<Row>,
+}
+
+impl Table {
+    pub fn from_grouped(grouped: HashMap<String, HashMap<String, u64>>) -> Self {
+        let rows: Vec<Row> = grouped
+            .into_iter()
+            .map(|(group, counts)| {
+                let total = counts.values().sum();
+                let items: Vec<(String, u64)> = counts
+                    .into_iter()
+                    .filter(|(item, _)| !item.is_empty())
+                    .collect();
+                Row {
+                    group,
+                    total,
+                    items,
+                }
+            })
+            .collect();
+        Self::sorted(rows)
+    }
+
+    fn sorted(mut rows: Vec<Row>) -> Self {
+        for row in rows.iter_mut() {
+            row.items
+                .sort_by(|a, b| b.1.cmp(&a.1).then_with(|| label_cmp(&a.0, &b.0)));
+        }
+        rows.sort_by(|a, b| {
+            b.total
+                .cmp(&a.total)
+                .then_with(|| label_cmp(&a.group, &b.group))
+        });
+        Table { rows }
+    }
+
+    /// adds the counts of another table. row totals are summed rather than recomputed,
+    /// as they can include counts which are not listed as items
+    pub fn merge(&mut self, other: &Table) {
+        let mut merged: HashMap<&str, (u64, HashMap<&str, u64>)> = HashMap::new();
+        for row in self.rows.iter().chain(&other.rows) {
+            let (total, items) = merged.entry(&row.group).or_default();
+            *total += row.total;
+            for (item, count) in &row.items {
+                *items.entry(item).or_default() += count;
+            }
+        }
+        let rows = merged
+            .into_iter()
+            .map(|(group, (total, items))| Row {
+                group: group.to_string(),
+                total,
+                items: items
+                    .into_iter()
+                    .map(|(item, count)| (item.to_string(), count))
+                    .collect(),
+            })
+            .collect();
+        *self = Self::sorted(rows);
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        for Row {
+            group,
+            total,
+            items,
+        } in &self.rows
+        {
+            write!(out, "{group}:{total}")?;
+            for (item, count) in items {
+                write!(out, " {item}:{count}")?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// everything counted within a window of a capture.
+/// the window is bounded by the wall clock when it was opened and closed, while flows carry the
+/// capture timestamps of their packets, which may lag behind by the capture buffering
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Digest {
+    /// unix time of the start of the window, by the wall clock
+    pub start: Duration,
+    /// unix time of the end of the window, by the wall clock
+    pub end: Duration,
+    /// whether this is one of a series of windows, rather than the whole capture
+    pub periodic: bool,
+    pub connections: u64,
+    /// connections per direction and destination port, ordered
+    pub ports: Vec<(Direction, u16, u64)>,
+    /// distinct sources of inbound connections
+    pub sources: u64,
+    /// packets dropped by the capture
+    pub dropped: u64,
+    /// counts of each distinct connection key
+    pub flows: Vec<Flow>,
+    pub sections: Vec<Section>,
+}
+
+impl Digest {
+    /// the sections as text, after a `# window <start> <end>` line if periodic
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        if self.periodic {
+            writeln!(
+                out,
+                "# window {} {}",
+                self.start.as_secs(),
+                self.end.as_secs()
+            )?;
+        }
+        for section in &self.sections {
+            section.write(out)?;
+        }
+        Ok(())
+    }
+
+    /// the inbound connections of each source and port as CSV rows, with the busiest first.
+    /// outbound connections are left out, as their source is this host, and destinations are
+    /// summed. dimensions outside of the key are left empty
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut rows: HashMap<CsvKey, u64> = HashMap::new();
+        for flow in self
+            .flows
+            .iter()
+            .filter(|f| f.key.direction == Direction::In)
+        {
+            let source = flow.key.source_ip.map(|ip| ip.to_string());
+            *rows
+                .entry((source.unwrap_or_default(), flow.key.dest_port))
+                .or_default() += flow.count;
+        }
+        write_csv_rows(out, self.start, self.end, rows)
+    }
+
+    /// adds the counts of another digest, and extends the window to cover both.
+    /// distinct sources can't be summed, so the larger count is kept as a lower bound
+    pub fn merge(&mut self, other: &Digest) {
+        if other.end > Duration::ZERO {
+            if self.end == Duration::ZERO {
+                (self.start, self.end) = (other.start, other.end);
+            } else {
+                self.start = self.start.min(other.start);
+                self.end = self.end.max(other.end);
+            }
+        }
+        self.periodic |= other.periodic;
+        self.connections += other.connections;
+        self.sources = self.sources.max(other.sources);
+        self.dropped += other.dropped;
+
+        let mut ports: HashMap<(Direction, u16), u64> = HashMap::new();
+        for &(direction, port, count) in self.ports.iter().chain(&other.ports) {
+            *ports.entry((direction, port)).or_default() += count;
+        }
+        self.ports = ports
+            .into_iter()
+            .map(|((direction, port), count)| (direction, port, count))
+            .collect();
+        self.ports.sort();
+
+        let mut flows: HashMap<ConnectionKey, Flow> = HashMap::new();
+        for flow in self.flows.iter().chain(&other.flows) {
+            flows
+                .entry(flow.key)
+                .and_modify(|merged| {
+                    merged.count += flow.count;
+                    merged.first = merged.first.min(flow.first);
+                    merged.last = merged.last.max(flow.last);
+                })
+                .or_insert(*flow);
+        }
+        self.flows = flows.into_values().collect();
+
+        for section in &other.sections {
+            match self.sections.iter_mut().find(|s| s.title == section.title) {
+                Some(merged) => merged.table.merge(&section.table),
+                None => self.sections.push(section.clone()),
+            }
+        }
+    }
+}
+
+/// source and port of a CSV row
+pub(crate) type CsvKey = (String, Option<u16>);
+
+/// CSV rows of source, port and count within a window, with the busiest first
+pub(crate) fn write_csv_rows<W, I>(
+    out: &mut W,
+    start: Duration,
+    end: Duration,
+    rows: I,
+) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (CsvKey, u64)>,
+{
+    let (start, end) = (rfc3339(start), rfc3339(end));
+    let mut rows: Vec<(CsvKey, u64)> = rows.into_iter().collect();
+    rows.sort_by(|((a, p), x), ((b, q), y)| (Reverse(x), p, a).cmp(&(Reverse(y), q, b)));
+    for ((source, port), count) in rows {
+        let port = port.map(|port| port.to_string()).unwrap_or_default();
+        writeln!(out, "{start},{end},{},{port},{count}", csv_field(&source))?;
+    }
+    Ok(())
+}
+
+/// a titled table of a digest, with the names of the labels in its rows and items
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// written as a header line when a digest has more than one table
+    pub title: Option<String>,
+    /// name of the row label, followed by the names of the parts of an item label
+    pub columns: Vec<String>,
+    pub table: Table,
+}
+
+/// one count of a section, with its labels named by column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub labels: Vec<(&'a str, &'a str)>,
+    pub count: u64,
+}
+
+impl Section {
+    pub fn new(title: Option<String>, columns: &[&str], table: Table) -> Self {
+        Section {
+            title,
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            table,
+        }
+    }
+
+    /// titled by the grouping, with a column per dimension
+    pub fn grouped(title: Option<String>, view: &GroupBy, table: Table) -> Self {
+        Section {
+            title,
+            columns: view.dimensions().iter().map(|d| d.to_string()).collect(),
+            table,
+        }
+    }
+
+    /// one entry per item, or per row when rows have no items
+    pub fn entries(&self) -> Vec<Entry<'_>> {
+        let mut entries = Vec::new();
+        for row in &self.table.rows {
+            let group = (
+                self.columns.first().map_or("", String::as_str),
+                row.group.as_str(),
+            );
+            if row.items.is_empty() {
+                entries.push(Entry {
+                    labels: vec![group],
+                    count: row.total,
+                });
+            }
+            for (item, count) in &row.items {
+                let labels = std::iter::once(group)
+                    .chain(
+                        self.columns
+                            .iter()
+                            .skip(1)
+                            .map(String::as_str)
+                            .zip(item.split('/')),
+                    )
+                    .collect();
+                entries.push(Entry {
+                    labels,
+                    count: *count,
+                });
+            }
+        }
+        entries
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        if let Some(title) = &self.title {
+            writeln!(out, "# {title}")?;
+        }
+        self.table.write(out)
+    }
+}
+
+/// numeric labels (ports) are ordered by value, everything else lexically
+fn label_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    (a.parse::<u64>().ok(), a).cmp(&(b.parse::<u64>().ok(), b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::aggregate::{csv_field, Digest, Entry, GroupBy, Section};
+    use crate::capture::Direction;
+    use crate::collector::{ConnectionKey, Flow, KeySpec};
+    use crate::sensitive::IpAddress;
+
+    fn records() -> Vec<(ConnectionKey, u64)> {
+        let ip1 = IpAddress::V4([10, 0, 0, 1]);
+        let ip2 = IpAddress::V4([10, 0, 0, 2]);
+        [(ip1, 22, 3), (ip1, 80, 1), (ip2, 80, 4), (ip2, 443, 1)]
+            .into_iter()
+            .map(|(ip, port, count)| {
+                let key = ConnectionKey {
+                    source_ip: Some(ip),
+                    dest_port: Some(port),
+                    ..Default::default()
+                };
+                (key, count)
+            })
+            .collect()
+    }
+
+    fn digest(group_by: &str) -> String {
+        let records = records();
+        let table = group_by
+            .parse::<GroupBy>()
+            .unwrap()
+            .aggregate(records.iter().map(|(k, c)| (k, c)))
+            .unwrap();
+        let mut out = Vec::new();
+        table.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_group_by_port_then_source() {
+        let ip1 = IpAddress::V4([10, 0, 0, 1]);
+        let ip2 = IpAddress::V4([10, 0, 0, 2]);
+        assert_eq!(
+            digest("dport,src"),
+            format!(
+                "80:5 {ip2}:4 {ip1}:1\n\
+                 22:3 {ip1}:3\n\
+                 443:1 {ip2}:1\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_group_by_port_totals() {
+        assert_eq!(digest("port"), "80:5\n22:3\n443:1\n");
+    }
+
+    #[test]
+    fn test_section_entries() {
+        let records = records();
+        let view: GroupBy = "dport,src".parse().unwrap();
+        let table = view.aggregate(records.iter().map(|(k, c)| (k, c))).unwrap();
+        let section = Section::grouped(None, &view, table);
+
+        let ip2 = IpAddress::V4([10, 0, 0, 2]).to_string();
+        let entries = section.entries();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(
+            entries[0],
+            Entry {
+                labels: vec![("dport", "80"), ("src", &ip2)],
+                count: 4
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let ip2 = IpAddress::V4([10, 0, 0, 2]);
+        let mut records = records();
+        // summed with the row of the same source and port
+        records.push((
+            ConnectionKey {
+                source_ip: Some(ip2),
+                dest_ip: Some(IpAddress::V4([10, 0, 0, 9])),
+                dest_port: Some(80),
+                ..Default::default()
+            },
+            2,
+        ));
+        // left out
+        records.push((
+            ConnectionKey {
+                direction: Direction::Out,
+                dest_port: Some(80),
+                ..Default::default()
+            },
+            7,
+        ));
+        let flows = records
+            .into_iter()
+            .map(|(key, count)| Flow {
+                key,
+                count,
+                first: Duration::ZERO,
+                last: Duration::ZERO,
+            })
+            .collect();
+        let digest = Digest {
+            start: Duration::from_secs(1697723025),
+            end: Duration::from_secs(1697723085),
+            flows,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        digest.write_csv(&mut out).unwrap();
+
+        let lines: Vec<String> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            format!("2023-10-19T13:43:45.000000Z,2023-10-19T13:44:45.000000Z,{ip2},80,6")
+        );
+        assert!(lines[3].ends_with(",443,1"));
+
+        assert_eq!(csv_field("fp 1"), "fp 1");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_validate() {
+        let key = KeySpec::default();
+        assert!("dport,src"
+            .parse::<GroupBy>()
+            .unwrap()
+            .validate(&key)
+            .is_ok());
+        assert!("dst".parse::<GroupBy>().unwrap().validate(&key).is_err());
+    }
+}
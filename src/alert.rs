@@ -0,0 +1,229 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde_json::json;
+
+use crate::capture::{Direction, ExtractedHeaders};
+use crate::hooks::{Action, Hook};
+use crate::sensitive::IpAddress;
+
+/// streaming analysis of counted connections
+pub trait Detector {
+    /// inspects a connection as soon as it is counted, adding any alerts it raises
+    fn observe(
+        &mut self,
+        direction: Direction,
+        headers: &ExtractedHeaders,
+        alerts: &mut Vec<Alert>,
+    );
+
+    /// inspects a SYN-ACK sent by this host, if answers were requested
+    fn observe_answer(&mut self, _headers: &ExtractedHeaders, _alerts: &mut Vec<Alert>) {}
+
+    /// whether SYN-ACKs should be captured for this detector
+    fn wants_answers(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    /// one source connected to many ports
+    VerticalScan,
+    /// one source connected to many hosts on the same port
+    HorizontalScan,
+    /// SYNs to a port arrived much faster than usual
+    SynFlood,
+    /// many SYNs to a port were not answered
+    UnansweredSyns,
+    /// a rule counted too many connections
+    ConnectionThreshold,
+    /// a rule counted too many distinct sources
+    SourceThreshold,
+}
+
+impl AlertKind {
+    pub const ALL: [AlertKind; 6] = [
+        AlertKind::VerticalScan,
+        AlertKind::HorizontalScan,
+        AlertKind::SynFlood,
+        AlertKind::UnansweredSyns,
+        AlertKind::ConnectionThreshold,
+        AlertKind::SourceThreshold,
+    ];
+
+    /// what the alert value measures
+    pub fn measure(&self) -> &'static str {
+        match self {
+            AlertKind::VerticalScan => "ports",
+            AlertKind::HorizontalScan => "hosts",
+            AlertKind::SynFlood => "syns",
+            AlertKind::UnansweredSyns => "unanswered-pct",
+            AlertKind::ConnectionThreshold => "connections",
+            AlertKind::SourceThreshold => "sources",
+        }
+    }
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AlertKind::VerticalScan => "vertical-scan",
+            AlertKind::HorizontalScan => "horizontal-scan",
+            AlertKind::SynFlood => "syn-flood",
+            AlertKind::UnansweredSyns => "unanswered-syns",
+            AlertKind::ConnectionThreshold => "connection-threshold",
+            AlertKind::SourceThreshold => "source-threshold",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    /// capture time of the connection which crossed the threshold, since the unix epoch
+    pub ts: Duration,
+    pub kind: AlertKind,
+    pub direction: Direction,
+    pub source: Option<IpAddress>,
+    pub port: Option<u16>,
+    /// observed value which crossed the threshold
+    pub value: u64,
+    pub threshold: u64,
+    pub window: Duration,
+    /// condition of the rule which raised the alert, if any
+    pub rule: Option<String>,
+    /// position of that rule among the loaded rules
+    pub rule_index: Option<usize>,
+}
+
+impl Alert {
+    /// event passed to alert hooks
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "ts": self.ts.as_secs_f64(),
+            "kind": self.kind.to_string(),
+            "direction": self.direction.to_string(),
+            "source": self.source.map(|s| s.to_string()),
+            "port": self.port,
+            "measure": self.kind.measure(),
+            "value": self.value,
+            "threshold": self.threshold,
+            "window": self.window.as_secs(),
+            "rule": self.rule,
+            "message": self.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.direction)?;
+        if let Some(source) = self.source {
+            write!(f, " source={source}")?;
+        }
+        if let Some(port) = self.port {
+            write!(f, " port={port}")?;
+        }
+        write!(
+            f,
+            " {}={} threshold={} window={}s",
+            self.kind.measure(),
+            self.value,
+            self.threshold,
+            self.window.as_secs()
+        )?;
+        if let Some(rule) = &self.rule {
+            write!(f, " rule=\"{rule}\"")?;
+        }
+        Ok(())
+    }
+}
+
+/// actions waiting for the worker thread. further actions are dropped when it is full
+const PENDING_ACTIONS: usize = 64;
+
+type Job = (Action, Arc<Vec<u8>>);
+
+/// destinations for alerts. every alert is logged as a warning
+#[derive(Default)]
+pub struct AlertOutputs {
+    file: Option<LineWriter<File>>,
+    hooks: Vec<Hook>,
+    worker: Option<(SyncSender<Job>, JoinHandle<()>)>,
+}
+
+impl AlertOutputs {
+    /// also append alerts to a file, one per line prefixed with a unix timestamp
+    pub fn file<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())
+            .with_context(|| format!("open alert file {}", path.as_ref().display()))?;
+        self.file = Some(LineWriter::new(file));
+        Ok(self)
+    }
+
+    /// also take an action for matching alerts. actions run one at a time on a background thread
+    pub fn hook(mut self, hook: Hook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    pub fn emit(&mut self, alert: &Alert) -> Result<()> {
+        warn!("alert: {alert}");
+
+        let mut event = None;
+        for hook in self.hooks.iter_mut() {
+            if !hook.accept(alert) {
+                continue;
+            }
+            let event: Arc<Vec<u8>> = event
+                .get_or_insert_with(|| Arc::new(alert.to_json().to_string().into_bytes()))
+                .clone();
+            let (jobs, _) = self.worker.get_or_insert_with(spawn_worker);
+            match jobs.try_send((hook.action().clone(), event)) {
+                Ok(()) => (),
+                Err(TrySendError::Full((action, _))) => {
+                    warn!("alert action {action:?} dropped, too many actions pending")
+                }
+                Err(TrySendError::Disconnected(_)) => warn!("alert action worker stopped"),
+            }
+        }
+
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{} {alert}", alert.ts.as_secs()).context("write alert file")?;
+        }
+        Ok(())
+    }
+
+    /// waits for pending actions to run
+    pub fn finish(&mut self) {
+        if let Some((jobs, handle)) = self.worker.take() {
+            drop(jobs);
+            if handle.join().is_err() {
+                warn!("alert action panicked");
+            }
+        }
+    }
+}
+
+fn spawn_worker() -> (SyncSender<Job>, JoinHandle<()>) {
+    let (jobs, pending) = mpsc::sync_channel::<Job>(PENDING_ACTIONS);
+    let handle = thread::spawn(move || {
+        for (action, event) in pending {
+            if let Err(e) = action.run(&event) {
+                warn!("alert action {action:?} failed: {e:#}");
+            }
+        }
+    });
+    (jobs, handle)
+}
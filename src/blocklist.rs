@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error, Result};
+
+use crate::capture::Direction;
+use crate::collector::Collector;
+use crate::sensitive::IpAddress;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// script for `nft -f`
+    #[default]
+    Nftables,
+    /// input for `ipset restore`
+    Ipset,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "nft" | "nftables" => Ok(Format::Nftables),
+            "ipset" => Ok(Format::Ipset),
+            _ => Err(anyhow!(
+                "unknown blocklist format: {s} (expected nft or ipset)"
+            )),
+        }
+    }
+}
+
+/// sources which made more inbound connections than a threshold, as a firewall set.
+/// addresses are written in the clear, even when the "privacy" feature is enabled.
+///
+/// connections are counted across periodic windows, so a listed source stays listed until the
+/// capture ends. a source under the threshold is forgotten after a window without connections.
+///
+/// IPv4 and IPv6 addresses go to separate sets, named after the blocklist with a 4 or 6 suffix.
+/// for nftables, the sets belong to an `inet` table of the same name.
+#[derive(Debug, Clone)]
+pub struct Blocklist {
+    path: PathBuf,
+    format: Format,
+    name: String,
+    threshold: u64,
+    timeout: Option<Duration>,
+    /// inbound connections per source, while it is listed or still connecting
+    counts: BTreeMap<IpAddress, u64>,
+}
+
+impl Blocklist {
+    pub fn new<P: Into<PathBuf>>(
+        path: P,
+        format: Format,
+        name: &str,
+        threshold: u64,
+        timeout: Option<Duration>,
+    ) -> Self {
+        Blocklist {
+            path: path.into(),
+            format,
+            name: name.to_string(),
+            threshold,
+            timeout,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// adds the inbound connections counted in the current window of the collector,
+    /// and forgets sources under the threshold which made none
+    pub fn add(&mut self, collector: &Collector) {
+        let mut window: BTreeMap<IpAddress, u64> = BTreeMap::new();
+        for (key, &count) in collector.records() {
+            if let (Direction::In, Some(source)) = (key.direction, key.source_ip) {
+                *window.entry(source).or_default() += count;
+            }
+        }
+        let threshold = self.threshold;
+        self.counts
+            .retain(|source, count| *count > threshold || window.contains_key(source));
+        for (source, count) in window {
+            *self.counts.entry(source).or_default() += count;
+        }
+    }
+
+    /// sources above the threshold, with their connection counts
+    pub fn sources(&self) -> BTreeMap<IpAddress, u64> {
+        let mut counts = self.counts.clone();
+        counts.retain(|_, count| *count > self.threshold);
+        counts
+    }
+
+    pub fn render(&self, sources: &BTreeMap<IpAddress, u64>) -> String {
+        let (v4, v6): (Vec<IpAddr>, Vec<IpAddr>) = sources
+            .keys()
+            .map(IpAddress::reveal)
+            .partition(IpAddr::is_ipv4);
+        let sets = [
+            (format!("{}4", self.name), v4),
+            (format!("{}6", self.name), v6),
+        ];
+
+        let mut out = String::new();
+        match self.format {
+            Format::Nftables => {
+                let flags = if self.timeout.is_some() {
+                    " flags timeout;"
+                } else {
+                    ""
+                };
+                let timeout = self
+                    .timeout
+                    .map(|t| format!(" timeout {}s", t.as_secs()))
+                    .unwrap_or_default();
+                writeln!(out, "add table inet {}", self.name).unwrap();
+                for ((set, addrs), kind) in sets.iter().zip(["ipv4_addr", "ipv6_addr"]) {
+                    writeln!(
+                        out,
+                        "add set inet {} {set} {{ type {kind};{flags} }}",
+                        self.name
+                    )
+                    .unwrap();
+                    if addrs.is_empty() {
+                        continue;
+                    }
+                    let elements: Vec<String> =
+                        addrs.iter().map(|a| format!("{a}{timeout}")).collect();
+                    writeln!(
+                        out,
+                        "add element inet {} {set} {{ {} }}",
+                        self.name,
+                        elements.join(", ")
+                    )
+                    .unwrap();
+                }
+            }
+            Format::Ipset => {
+                let timeout = self
+                    .timeout
+                    .map(|t| format!(" timeout {}", t.as_secs()))
+                    .unwrap_or_default();
+                for ((set, addrs), family) in sets.iter().zip(["inet", "inet6"]) {
+                    writeln!(out, "create {set} hash:ip family {family}{timeout} -exist").unwrap();
+                    for addr in addrs {
+                        writeln!(out, "add {set} {addr}{timeout} -exist").unwrap();
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// replaces the blocklist file, so that a loader never sees a partial list.
+    /// returns the number of sources listed
+    pub fn write(&self) -> Result<usize> {
+        let sources = self.sources();
+        write_atomic(&self.path, self.render(&sources).as_bytes())
+            .with_context(|| format!("write blocklist {}", self.path.display()))?;
+        Ok(sources.len())
+    }
+}
+
+/// writes to a temporary file next to the destination, then renames it into place
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp_name = path
+        .file_name()
+        .ok_or(anyhow!("not a file path"))?
+        .to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::fs;
+    use std::time::Duration;
+
+    use crate::blocklist::{Blocklist, Format};
+    use crate::capture::Direction;
+    use crate::collector::{Collector, ConnectionKey};
+    use crate::sensitive::IpAddress;
+
+    fn sources() -> BTreeMap<IpAddress, u64> {
+        BTreeMap::from([
+            (IpAddress::V4([192, 0, 2, 1]), 150),
+            (IpAddress::V4([192, 0, 2, 7]), 101),
+            (
+                IpAddress::V6([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                200,
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_render_nftables() {
+        let blocklist = Blocklist::new(
+            "/dev/null",
+            Format::Nftables,
+            "pulso",
+            100,
+            Some(Duration::from_secs(3600)),
+        );
+        assert_eq!(
+            blocklist.render(&sources()),
+            "add table inet pulso\n\
+             add set inet pulso pulso4 { type ipv4_addr; flags timeout; }\n\
+             add element inet pulso pulso4 { 192.0.2.1 timeout 3600s, 192.0.2.7 timeout 3600s }\n\
+             add set inet pulso pulso6 { type ipv6_addr; flags timeout; }\n\
+             add element inet pulso pulso6 { 2001:db8::1 timeout 3600s }\n"
+        );
+    }
+
+    #[test]
+    fn test_render_ipset() {
+        let blocklist = Blocklist::new("/dev/null", Format::Ipset, "ssh", 100, None);
+        let mut sources = sources();
+        sources.retain(|ip, _| matches!(ip, IpAddress::V4(_)));
+        assert_eq!(
+            blocklist.render(&sources),
+            "create ssh4 hash:ip family inet -exist\n\
+             add ssh4 192.0.2.1 -exist\n\
+             add ssh4 192.0.2.7 -exist\n\
+             create ssh6 hash:ip family inet6 -exist\n"
+        );
+    }
+
+    #[test]
+    fn test_write_sources_above_threshold() {
+        let path = std::env::temp_dir().join(format!("pulso-blocklist-{}", std::process::id()));
+        let mut blocklist = Blocklist::new(&path, Format::Ipset, "pulso", 2, None);
+
+        let key = |ip: u8, port: u16| ConnectionKey {
+            direction: Direction::In,
+            source_ip: Some(IpAddress::V4([192, 0, 2, ip])),
+            dest_ip: None,
+            dest_port: Some(port),
+        };
+        let mut collector = Collector::default();
+        collector.connections = HashMap::from([(key(1, 22), 2), (key(1, 80), 1), (key(2, 22), 2)]);
+        blocklist.add(&collector);
+
+        assert_eq!(blocklist.write().unwrap(), 1);
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("add pulso4 192.0.2.1 -exist\n"));
+        assert!(!contents.contains("192.0.2.2"));
+
+        // counted across windows, so earlier sources stay listed
+        collector.connections = HashMap::from([(key(2, 443), 1), (key(3, 22), 1)]);
+        blocklist.add(&collector);
+        assert_eq!(blocklist.write().unwrap(), 2);
+
+        // a source under the threshold is forgotten after a window without connections
+        collector.connections = HashMap::from([(key(3, 22), 1)]);
+        blocklist.add(&collector);
+        collector.connections = HashMap::new();
+        blocklist.add(&collector);
+        collector.connections = HashMap::from([(key(3, 22), 1)]);
+        blocklist.add(&collector);
+        assert_eq!(blocklist.write().unwrap(), 2);
+        assert_eq!(blocklist.counts.len(), 3);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,25 +1,155 @@
-use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error, Result};
 use etherparse::{InternetSlice, SlicedPacket, TransportSlice};
 use libc::timeval;
 use log::debug;
-use pcap::{self, Active, Capture, Device, Direction, Packet, PacketCodec, PacketHeader};
+use pcap::{self, Active, Capture, Device, Packet, PacketCodec, PacketHeader};
 
 use crate::sensitive::IpAddress;
 
+/// side of the capture device that a connection was initiated from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Direction {
+    #[default]
+    In,
+    Out,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Direction::In => "inbound",
+            Direction::Out => "outbound",
+        })
+    }
+}
+
+impl Direction {
+    /// the name accepted by [FromStr]
+    pub fn short(&self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "in" => Ok(Direction::In),
+            "out" => Ok(Direction::Out),
+            other => Err(anyhow!("unknown direction: {other} (expected in or out)")),
+        }
+    }
+}
+
+/// directions monitored by a capture session
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Directions {
+    #[default]
+    In,
+    Out,
+    Both,
+}
+
+impl Directions {
+    pub fn list(&self) -> &'static [Direction] {
+        match self {
+            Directions::In => &[Direction::In],
+            Directions::Out => &[Direction::Out],
+            Directions::Both => &[Direction::In, Direction::Out],
+        }
+    }
+}
+
+impl FromStr for Directions {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "in" => Ok(Directions::In),
+            "out" => Ok(Directions::Out),
+            "both" => Ok(Directions::Both),
+            other => Err(anyhow!(
+                "unknown direction: {other} (expected in, out or both)"
+            )),
+        }
+    }
+}
+
+/// handshake segment selected by the capture filter
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Handshake {
+    /// a new connection
+    #[default]
+    Syn,
+    /// a new connection being accepted
+    SynAck,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PacketOwned {
     pub capture_header: PacketHeader,
+    pub direction: Direction,
+    pub handshake: Handshake,
     data: Box<[u8]>,
 }
 
 #[derive(Debug)]
 pub struct ExtractedHeaders {
     pub source_ip: IpAddress,
+    pub source_port: u16,
+    pub dest_ip: IpAddress,
     pub dest_port: u16,
     pub capture_ts: timeval,
+    pub syn: SynHeaders,
+}
+
+impl ExtractedHeaders {
+    /// capture time since the unix epoch
+    pub fn timestamp(&self) -> Duration {
+        Duration::new(
+            self.capture_ts.tv_sec as u64,
+            self.capture_ts.tv_usec as u32 * 1000,
+        )
+    }
+}
+
+/// fields of the SYN which vary between TCP/IP stacks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SynHeaders {
+    /// time to live (IPv4) or hop limit (IPv6)
+    pub ttl: u8,
+    /// identification field, IPv4 only
+    pub ip_id: Option<u16>,
+    pub sequence_number: u32,
+    pub window_size: u16,
+    /// raw TCP options
+    pub options: Vec<u8>,
 }
 
 impl PacketOwned {
+    /// an ethernet frame captured elsewhere, such as by a collector on another thread
+    pub fn new(
+        capture_header: PacketHeader,
+        direction: Direction,
+        handshake: Handshake,
+        data: &[u8],
+    ) -> Self {
+        PacketOwned {
+            capture_header,
+            direction,
+            handshake,
+            data: data.into(),
+        }
+    }
+
     pub fn headers(&self) -> Result<ExtractedHeaders> {
         match SlicedPacket::from_ethernet(&self.data) {
             Ok(SlicedPacket {
@@ -27,14 +157,33 @@ impl PacketOwned {
                 transport: Some(TransportSlice::Tcp(tcp_headers)),
                 ..
             }) => {
-                let source_ip = match ip_headers {
-                    InternetSlice::Ipv6(headers, _) => IpAddress::V6(headers.source()),
-                    InternetSlice::Ipv4(headers, _) => IpAddress::V4(headers.source()),
+                let (source_ip, dest_ip, ttl, ip_id) = match ip_headers {
+                    InternetSlice::Ipv6(headers, _) => (
+                        IpAddress::V6(headers.source()),
+                        IpAddress::V6(headers.destination()),
+                        headers.hop_limit(),
+                        None,
+                    ),
+                    InternetSlice::Ipv4(headers, _) => (
+                        IpAddress::V4(headers.source()),
+                        IpAddress::V4(headers.destination()),
+                        headers.ttl(),
+                        Some(headers.identification()),
+                    ),
                 };
                 Ok(ExtractedHeaders {
                     source_ip,
+                    source_port: tcp_headers.source_port(),
+                    dest_ip,
                     dest_port: tcp_headers.destination_port(),
                     capture_ts: self.capture_header.ts,
+                    syn: SynHeaders {
+                        ttl,
+                        ip_id,
+                        sequence_number: tcp_headers.sequence_number(),
+                        window_size: tcp_headers.window_size(),
+                        options: tcp_headers.options().to_vec(),
+                    },
                 })
             }
             Ok(skipped) => Err(anyhow!(
@@ -51,7 +200,10 @@ impl PacketOwned {
     }
 }
 
-pub struct Codec;
+pub struct Codec {
+    pub direction: Direction,
+    pub handshake: Handshake,
+}
 
 impl PacketCodec for Codec {
     type Item = PacketOwned;
@@ -59,22 +211,42 @@ impl PacketCodec for Codec {
     fn decode(&mut self, packet: Packet) -> Self::Item {
         PacketOwned {
             capture_header: *packet.header,
+            direction: self.direction,
+            handshake: self.handshake,
             data: packet.data.into(),
         }
     }
 }
 
-pub fn capture_from_device(device_name: &str) -> Result<Capture<Active>> {
-    let device = Device::list()
+/// enough for an ethernet header with two vlan tags, an ipv4 header with options and a tcp header
+/// with options
+const SNAPLEN: i32 = 14 + 2 * 4 + 60 + 60;
+
+fn find_device(device_name: &str) -> Result<Device> {
+    Device::list()
         .context("list devices")?
         .into_iter()
         .find(|d| d.name == device_name)
-        .ok_or(anyhow!("device not found: {}", device_name))?;
+        .ok_or(anyhow!("device not found: {}", device_name))
+}
+
+/// whether the device is a loopback interface, on which every packet is seen both leaving and
+/// arriving
+pub fn is_loopback(device_name: &str) -> Result<bool> {
+    Ok(find_device(device_name)?.flags.is_loopback())
+}
+
+pub fn capture_from_device(
+    device_name: &str,
+    direction: Direction,
+    handshake: Handshake,
+) -> Result<Capture<Active>> {
+    let device = find_device(device_name)?;
     debug!("{:?}", device);
 
     let mut capture = Capture::from_device(device)
         .context("capture from device")?
-        .snaplen(96) // only tcp headers
+        .snaplen(SNAPLEN)
         .timeout(1000) // read network buffer at least once per second
         .immediate_mode(cfg!(feature = "immediate_mode")) // for integration testing
         .open()
@@ -90,21 +262,30 @@ pub fn capture_from_device(device_name: &str) -> Result<Capture<Active>> {
     13:43:45.070560 IP localhost.34644 > localhost.italk: Flags [F.],
     13:43:45.070668 IP localhost.italk > localhost.34644: Flags [F.],
     13:43:45.070684 IP localhost.34644 > localhost.italk: Flags [.],
-    Capturing only the first packet for now (SYN & !ACK)
+    Capturing only the first packet for now (SYN & !ACK), sent by the peer (inbound)
+    or by this host (outbound). The second packet (SYN & ACK) is captured separately
+    when needed to tell whether connections are being answered.
     https://wiki.wireshark.org/TCP_3_way_handshaking
     https://www.ietf.org/rfc/rfc9293.html#section-3.5
     */
     capture
-        .direction(Direction::In)
+        .direction(match direction {
+            Direction::In => pcap::Direction::In,
+            Direction::Out => pcap::Direction::Out,
+        })
         .context("set capture direction")?;
 
-    capture
-        .filter(
+    let filter = match handshake {
+        Handshake::Syn => {
             "(ip6 and proto \\tcp and ip6[40+13]&0x2 != 0 and ip6[40+13]&0x10 = 0) \
-            or (ip and tcp[tcpflags] & (tcp-syn) != 0 and tcp[tcpflags] & (tcp-ack) = 0)",
-            true,
-        )
-        .context("set capture filter")?;
+            or (ip and tcp[tcpflags] & (tcp-syn) != 0 and tcp[tcpflags] & (tcp-ack) = 0)"
+        }
+        Handshake::SynAck => {
+            "(ip6 and proto \\tcp and ip6[40+13]&0x12 = 0x12) \
+            or (ip and tcp[tcpflags] & (tcp-syn|tcp-ack) = (tcp-syn|tcp-ack))"
+        }
+    };
+    capture.filter(filter, true).context("set capture filter")?;
 
     Ok(capture)
 }
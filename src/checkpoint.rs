@@ -0,0 +1,371 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde_json::{json, Value};
+
+use crate::alert::AlertKind;
+use crate::collector::{unix_now, Collector, ConnectionKey, Flow, State};
+use crate::fingerprint::Fingerprint;
+use crate::json::{array, each, get, nanos, number, optional, port, text, tuple, variant};
+use crate::scanner::Classification;
+use crate::sensitive::IpAddress;
+
+const FORMAT: &str = "pulso-checkpoint";
+const VERSION: u64 = 1;
+
+fn ip_json(ip: Option<IpAddress>) -> Value {
+    json!(ip.map(|ip| ip.reveal().to_string()))
+}
+
+/// the state as JSON. addresses are written as they are, so that they are still recognised
+/// as the same sources when the state is restored
+pub fn to_json(state: &State) -> Value {
+    let keys: Vec<Value> = state
+        .keys
+        .iter()
+        .map(|(direction, key)| json!([direction.short(), key.to_string()]))
+        .collect();
+    let flows: Vec<Value> = state
+        .flows
+        .iter()
+        .map(|flow| {
+            json!({
+                "direction": flow.key.direction.short(),
+                "source": ip_json(flow.key.source_ip),
+                "destination": ip_json(flow.key.dest_ip),
+                "port": flow.key.dest_port,
+                "count": flow.count,
+                "first": nanos(flow.first),
+                "last": nanos(flow.last),
+            })
+        })
+        .collect();
+    let ports: Vec<Value> = state
+        .ports
+        .iter()
+        .map(|(direction, port, count)| json!([direction.short(), port, count]))
+        .collect();
+    let sources: Vec<Value> = state.sources.iter().map(|ip| ip_json(Some(*ip))).collect();
+    let fingerprints = state.fingerprints.as_ref().map(|counts| {
+        counts
+            .iter()
+            .map(|(port, fingerprint, count)| {
+                let fingerprint = json!({
+                    "window_size": fingerprint.window_size,
+                    "option_kinds": fingerprint.option_kinds,
+                    "mss": fingerprint.mss,
+                    "window_scale": fingerprint.window_scale,
+                });
+                json!([port, fingerprint, count])
+            })
+            .collect::<Vec<Value>>()
+    });
+    let scanners = state.scanners.as_ref().map(|counts| {
+        counts
+            .iter()
+            .map(|(port, classification, count)| json!([port, classification.to_string(), count]))
+            .collect::<Vec<Value>>()
+    });
+    let alerts: Vec<Value> = state
+        .alerts
+        .iter()
+        .map(|(kind, port, count)| json!([kind.to_string(), port, count]))
+        .collect();
+    json!({
+        "format": FORMAT,
+        "version": VERSION,
+        "keys": keys,
+        "connection_count": state.connection_count,
+        "captured_bytes": state.captured_bytes,
+        "window_start": nanos(state.window_start),
+        "window_connections": state.window_connections,
+        "dropped": state.dropped,
+        "flows": flows,
+        "ports": ports,
+        "sources": sources,
+        "fingerprints": fingerprints,
+        "scanners": scanners,
+        "alerts": alerts,
+    })
+}
+
+fn ip(value: &Value) -> Result<IpAddress> {
+    Ok(IpAddress::from(text(value)?.parse::<IpAddr>()?))
+}
+
+fn flow(value: &Value) -> Result<Flow> {
+    Ok(Flow {
+        key: ConnectionKey {
+            direction: text(get(value, "direction")?)?.parse()?,
+            source_ip: optional(get(value, "source")?, ip)?,
+            dest_ip: optional(get(value, "destination")?, ip)?,
+            dest_port: optional(get(value, "port")?, port)?,
+        },
+        count: number(get(value, "count")?)?,
+        first: Duration::from_nanos(number(get(value, "first")?)?),
+        last: Duration::from_nanos(number(get(value, "last")?)?),
+    })
+}
+
+fn fingerprint(value: &Value) -> Result<Fingerprint> {
+    let option_kinds = array(get(value, "option_kinds")?)?
+        .iter()
+        .map(|kind| Ok(u8::try_from(number(kind)?)?))
+        .collect::<Result<_>>()?;
+    let window_scale = |value: &Value| Ok(u8::try_from(number(value)?)?);
+    Ok(Fingerprint {
+        window_size: port(get(value, "window_size")?)?,
+        option_kinds,
+        mss: optional(get(value, "mss")?, port)?,
+        window_scale: optional(get(value, "window_scale")?, window_scale)?,
+    })
+}
+
+/// reads a state written by [to_json], checking its format and version
+pub fn from_json(value: &Value) -> Result<State> {
+    if value.get("format").and_then(Value::as_str) != Some(FORMAT) {
+        bail!("not a pulso checkpoint");
+    }
+    let version = number(get(value, "version")?)?;
+    if version != VERSION {
+        bail!("unsupported checkpoint version {version} (expected {VERSION})");
+    }
+    let fingerprints = |state: &Value| {
+        each(state, "fingerprints", |count| {
+            let [port_value, fingerprint_value, count] = tuple(count)?;
+            Ok((
+                port(port_value)?,
+                fingerprint(fingerprint_value)?,
+                number(count)?,
+            ))
+        })
+    };
+    let scanners = |state: &Value| {
+        each(state, "scanners", |count| {
+            let [port_value, classification, count] = tuple(count)?;
+            Ok((
+                port(port_value)?,
+                variant(&Classification::ALL, classification)?,
+                number(count)?,
+            ))
+        })
+    };
+    Ok(State {
+        keys: each(value, "keys", |key| {
+            let [direction, key] = tuple(key)?;
+            Ok((text(direction)?.parse()?, text(key)?.parse()?))
+        })?,
+        connection_count: number(get(value, "connection_count")?)?,
+        captured_bytes: number(get(value, "captured_bytes")?)?,
+        window_start: Duration::from_nanos(number(get(value, "window_start")?)?),
+        window_connections: number(get(value, "window_connections")?)?,
+        dropped: number(get(value, "dropped")?)?,
+        flows: each(value, "flows", flow)?,
+        ports: each(value, "ports", |count| {
+            let [direction, port_value, count] = tuple(count)?;
+            Ok((text(direction)?.parse()?, port(port_value)?, number(count)?))
+        })?,
+        sources: each(value, "sources", ip)?,
+        fingerprints: optional(get(value, "fingerprints")?, |_| fingerprints(value))?,
+        scanners: optional(get(value, "scanners")?, |_| scanners(value))?,
+        alerts: each(value, "alerts", |count| {
+            let [kind, port_value, count] = tuple(count)?;
+            Ok((
+                variant(&AlertKind::ALL, kind)?,
+                optional(port_value, port)?,
+                number(count)?,
+            ))
+        })?,
+    })
+}
+
+/// collector state saved to a file, so that a restarted capture can continue its window.
+/// the file holds real addresses, so it is only readable by its owner.
+///
+/// only the counts are saved. detectors start over with empty windows and baselines, and alerts
+/// which were not emitted yet are lost
+pub struct Checkpoint {
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl Checkpoint {
+    pub fn new(path: &Path) -> Self {
+        Checkpoint {
+            path: path.to_path_buf(),
+            interval: Duration::from_secs(60),
+        }
+    }
+
+    /// how often the state is saved while capturing
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn save_interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// replaces the file with the current state, through a temporary file
+    pub fn save(&self, collector: &Collector) -> Result<()> {
+        let partial = self.path.with_extension("tmp");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&partial)
+            .with_context(|| format!("create {}", partial.display()))?;
+        let mut state = to_json(&collector.state());
+        state["saved"] = json!(nanos(unix_now()));
+        writeln!(file, "{state}")?;
+        file.sync_all()?;
+        fs::rename(&partial, &self.path)
+            .with_context(|| format!("rename {}", partial.display()))?;
+        debug!("saved checkpoint {}", self.path.display());
+        Ok(())
+    }
+
+    /// the state saved in a file, or None if there is no file
+    pub fn load(path: &Path) -> Result<Option<State>> {
+        let input = match fs::read_to_string(path) {
+            Ok(input) => input,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+        };
+        let value: Value = serde_json::from_str(&input).context("invalid json")?;
+        from_json(&value).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::alert::AlertKind;
+    use crate::capture::{Direction, Directions};
+    use crate::checkpoint::{from_json, to_json, Checkpoint};
+    use crate::collector::{Collector, ConnectionKey, Flow, KeySpec, State};
+    use crate::fingerprint::Fingerprint;
+    use crate::scanner::Classification;
+    use crate::sensitive::IpAddress;
+
+    fn state() -> State {
+        let source = IpAddress::V4([192, 0, 2, 1]);
+        State {
+            keys: vec![
+                (Direction::In, KeySpec::default_for(Direction::In)),
+                (Direction::Out, KeySpec::default_for(Direction::Out)),
+            ],
+            connection_count: 12,
+            captured_bytes: 900,
+            window_start: Duration::from_secs(1697723000),
+            window_connections: 5,
+            dropped: 1,
+            flows: vec![Flow {
+                key: ConnectionKey {
+                    direction: Direction::In,
+                    source_ip: Some(source),
+                    dest_ip: None,
+                    dest_port: Some(22),
+                },
+                count: 5,
+                first: Duration::from_millis(1697723001500),
+                last: Duration::from_secs(1697723059),
+            }],
+            ports: vec![(Direction::In, 22, 5)],
+            sources: vec![source, IpAddress::V6([1; 16])],
+            fingerprints: Some(vec![(
+                22,
+                Fingerprint {
+                    window_size: 64240,
+                    option_kinds: vec![2, 4, 8, 1, 3],
+                    mss: Some(1460),
+                    window_scale: None,
+                },
+                5,
+            )]),
+            scanners: None,
+            alerts: vec![(AlertKind::SynFlood, Some(22), 1)],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let state = state();
+        assert_eq!(from_json(&to_json(&state)).unwrap(), state);
+
+        let mut value = to_json(&state);
+        value["scanners"] = json!([[22, "zmap", 2]]);
+        let scanners = from_json(&value).unwrap().scanners;
+        assert_eq!(scanners, Some(vec![(22, Classification::Zmap, 2)]));
+    }
+
+    #[test]
+    fn test_validation() {
+        let error = |value: serde_json::Value| format!("{:#}", from_json(&value).unwrap_err());
+        assert_eq!(error(json!({ "counts": [] })), "not a pulso checkpoint");
+
+        let mut value = to_json(&state());
+        value["version"] = json!(2);
+        assert_eq!(
+            error(value),
+            "unsupported checkpoint version 2 (expected 1)"
+        );
+
+        let mut value = to_json(&state());
+        value["flows"][0]["port"] = json!(70000);
+        assert_eq!(
+            error(value),
+            "flows[0]: out of range integral type conversion attempted"
+        );
+
+        let mut value = to_json(&state());
+        value["alerts"][0][0] = json!("flood");
+        assert_eq!(error(value), "alerts[0]: unknown value: flood");
+    }
+
+    #[test]
+    fn test_save_and_restore() {
+        let path = std::env::temp_dir().join(format!("pulso-{}.checkpoint", std::process::id()));
+        let mut collector = Collector::new(Directions::Both, None);
+        collector.restore(state()).unwrap();
+        Checkpoint::new(&path).save(&collector).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let loaded = Checkpoint::load(&path).unwrap().unwrap();
+        let mut restored = Collector::new(Directions::Both, None);
+        restored.restore(loaded).unwrap();
+        let digest = restored
+            .take_digest(Duration::from_secs(1697723060))
+            .unwrap();
+        assert_eq!(digest.start, Duration::from_secs(1697723000));
+        assert_eq!((digest.connections, digest.sources), (5, 2));
+        assert_eq!(digest.flows, state().flows);
+
+        // fingerprints are not counted by this collector, so they are dropped
+        assert_eq!(restored.state().fingerprints, None);
+
+        let mut inbound = Collector::new(Directions::In, None);
+        let error = inbound.restore(state()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "state was counted by inbound src,dport, outbound dst,dport, not inbound src,dport"
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+    }
+}
@@ -1,86 +1,782 @@
-use std::collections::HashMap;
-use std::fmt::Write as _;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
 use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
-use log::debug;
+use anyhow::{anyhow, Context, Error, Result};
+use log::{debug, warn};
 
-use crate::capture::{ExtractedHeaders, PacketOwned};
+use crate::aggregate::{Digest, GroupBy, Section, Table};
+use crate::alert::{Alert, AlertKind, Detector};
+use crate::capture::{Direction, Directions, ExtractedHeaders, Handshake, PacketOwned};
+use crate::events::EventLog;
+use crate::fingerprint::{Fingerprint, FingerprintCounter};
+use crate::scanner::{Classification, ScannerCounter};
 use crate::sensitive::IpAddress;
 
-#[derive(Default)]
+/// an attribute of a connection which can be used to distinguish it from others
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dimension {
+    Source,
+    Destination,
+    DestPort,
+}
+
+impl Dimension {
+    /// formatted value of this dimension, if it was retained by the key spec
+    pub fn label(&self, key: &ConnectionKey) -> Option<String> {
+        match self {
+            Dimension::Source => key.source_ip.map(|ip| ip.to_string()),
+            Dimension::Destination => key.dest_ip.map(|ip| ip.to_string()),
+            Dimension::DestPort => key.dest_port.map(|port| port.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Dimension::Source => "src",
+            Dimension::Destination => "dst",
+            Dimension::DestPort => "dport",
+        })
+    }
+}
+
+impl FromStr for Dimension {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "src" => Ok(Dimension::Source),
+            "dst" => Ok(Dimension::Destination),
+            "dport" | "port" => Ok(Dimension::DestPort),
+            other => Err(anyhow!(
+                "unknown dimension: {other} (expected src, dst or dport)"
+            )),
+        }
+    }
+}
+
+/// parses a comma separated list of unique dimensions
+pub(crate) fn parse_dimensions(s: &str) -> Result<Vec<Dimension>> {
+    let mut dimensions = Vec::new();
+    for part in s.split(',') {
+        let dimension = part.parse()?;
+        if dimensions.contains(&dimension) {
+            return Err(anyhow!("duplicate dimension: {}", part.trim()));
+        }
+        dimensions.push(dimension);
+    }
+    Ok(dimensions)
+}
+
+pub(crate) fn fmt_dimensions(dimensions: &[Dimension], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (i, dimension) in dimensions.iter().enumerate() {
+        if i > 0 {
+            f.write_str(",")?;
+        }
+        write!(f, "{dimension}")?;
+    }
+    Ok(())
+}
+
+/// ordered list of dimensions used to count connections.
+/// the digest is grouped in the same order unless a different grouping is requested.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySpec(Vec<Dimension>);
+
+impl KeySpec {
+    pub fn dimensions(&self) -> &[Dimension] {
+        &self.0
+    }
+
+    pub fn contains(&self, dimension: Dimension) -> bool {
+        self.0.contains(&dimension)
+    }
+}
+
+impl KeySpec {
+    /// the remote peer and the port it connected to (inbound)
+    /// or the remote peer and the port that was connected to (outbound)
+    pub fn default_for(direction: Direction) -> Self {
+        match direction {
+            Direction::In => KeySpec(vec![Dimension::Source, Dimension::DestPort]),
+            Direction::Out => KeySpec(vec![Dimension::Destination, Dimension::DestPort]),
+        }
+    }
+}
+
+impl Default for KeySpec {
+    fn default() -> Self {
+        KeySpec::default_for(Direction::In)
+    }
+}
+
+impl fmt::Display for KeySpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_dimensions(&self.0, f)
+    }
+}
+
+impl FromStr for KeySpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(KeySpec(parse_dimensions(s)?))
+    }
+}
+
+/// connection attributes retained by the collector. dimensions outside of the key spec are None
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionKey {
+    pub direction: Direction,
+    pub source_ip: Option<IpAddress>,
+    pub dest_ip: Option<IpAddress>,
+    pub dest_port: Option<u16>,
+}
+
+impl ConnectionKey {
+    fn new(direction: Direction, headers: &ExtractedHeaders, spec: &KeySpec) -> Self {
+        ConnectionKey {
+            direction,
+            source_ip: Some(headers.source_ip).filter(|_| spec.contains(Dimension::Source)),
+            dest_ip: Some(headers.dest_ip).filter(|_| spec.contains(Dimension::Destination)),
+            dest_port: Some(headers.dest_port).filter(|_| spec.contains(Dimension::DestPort)),
+        }
+    }
+}
+
+/// connections counted under one key within a window, with the capture times of the first and last
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flow {
+    pub key: ConnectionKey,
+    pub count: u64,
+    pub first: Duration,
+    pub last: Duration,
+}
+
+/// counts which can be combined with those of another instance, such as a collector running on
+/// another thread or host. merging is associative and commutative
+pub trait Mergeable {
+    /// adds the counts of another instance, as if this one had counted them too
+    fn merge(&mut self, other: Self);
+}
+
+impl<K: Eq + Hash> Mergeable for HashMap<K, u64> {
+    fn merge(&mut self, other: Self) {
+        for (key, count) in other {
+            *self.entry(key).or_default() += count;
+        }
+    }
+}
+
+impl<T: Eq + Hash> Mergeable for HashSet<T> {
+    fn merge(&mut self, other: Self) {
+        self.extend(other);
+    }
+}
+
+impl<T: Mergeable> Mergeable for Option<T> {
+    /// counted if either is
+    fn merge(&mut self, other: Self) {
+        match (self.as_mut(), other) {
+            (Some(counts), Some(other)) => counts.merge(other),
+            (None, other) => *self = other,
+            (_, None) => (),
+        }
+    }
+}
+
 pub struct Collector {
+    directions: Directions,
+    inbound_key: KeySpec,
+    outbound_key: KeySpec,
+    views: Vec<GroupBy>,
+    interval: Option<Duration>,
     connection_count: u64,
     captured_bytes: u64,
-    connections: HashMap<(IpAddress, u16), u64>,
+    window_start: Duration,
+    window_connections: u64,
+    dropped: u64,
+    pub(crate) connections: HashMap<ConnectionKey, u64>,
+    /// capture times of the first and last connection counted under each key
+    spans: HashMap<ConnectionKey, (Duration, Duration)>,
+    ports: HashMap<(Direction, u16), u64>,
+    sources: HashSet<IpAddress>,
+    fingerprints: Option<FingerprintCounter>,
+    scanners: Option<ScannerCounter>,
+    detectors: Vec<Box<dyn Detector>>,
+    alerts: Vec<Alert>,
+    alert_counts: HashMap<(AlertKind, Option<u16>), u64>,
+    events: Option<EventLog>,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Collector::new(Directions::default(), None)
+    }
 }
 
 impl Collector {
+    /// uses the same key for both directions if provided, otherwise the default for each direction
+    pub fn new(directions: Directions, key: Option<KeySpec>) -> Self {
+        Collector {
+            directions,
+            inbound_key: key
+                .clone()
+                .unwrap_or_else(|| KeySpec::default_for(Direction::In)),
+            outbound_key: key.unwrap_or_else(|| KeySpec::default_for(Direction::Out)),
+            views: Vec::new(),
+            interval: None,
+            connection_count: 0,
+            captured_bytes: 0,
+            window_start: unix_now(),
+            window_connections: 0,
+            dropped: 0,
+            connections: HashMap::new(),
+            spans: HashMap::new(),
+            ports: HashMap::new(),
+            sources: HashSet::new(),
+            fingerprints: None,
+            scanners: None,
+            detectors: Vec::new(),
+            alerts: Vec::new(),
+            alert_counts: HashMap::new(),
+            events: None,
+        }
+    }
+
+    /// digest groupings, see [Collector::sections]
+    pub fn views(mut self, views: Vec<GroupBy>) -> Self {
+        self.views = views;
+        self
+    }
+
+    /// produce a digest at this interval instead of once at the end of the capture
+    pub fn interval(mut self, interval: Option<Duration>) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// analyse each counted connection as it arrives
+    pub fn detector<D: Detector + 'static>(mut self, detector: D) -> Self {
+        self.detectors.push(Box::new(detector));
+        self
+    }
+
+    /// count SYN fingerprints per destination port of inbound connections
+    pub fn fingerprints(mut self, enabled: bool) -> Self {
+        self.fingerprints = enabled.then(FingerprintCounter::default);
+        self
+    }
+
+    /// count inbound connections from known mass scanners separately from organic ones, per port
+    pub fn scanners(mut self, enabled: bool) -> Self {
+        self.scanners = enabled.then(ScannerCounter::default);
+        self
+    }
+
+    /// also write every counted connection to an event log
+    pub fn events(mut self, events: EventLog) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn directions(&self) -> Directions {
+        self.directions
+    }
+
+    pub fn digest_interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// whether SYN-ACKs sent by this host should be captured for the detectors
+    pub fn wants_answers(&self) -> bool {
+        self.detectors.iter().any(|d| d.wants_answers())
+    }
+
+    pub fn key(&self, direction: Direction) -> &KeySpec {
+        match direction {
+            Direction::In => &self.inbound_key,
+            Direction::Out => &self.outbound_key,
+        }
+    }
+
+    /// checks that each view can be produced for every monitored direction
+    pub fn validate(&self) -> Result<()> {
+        for &direction in self.directions.list() {
+            for view in &self.views {
+                view.validate(self.key(direction))
+                    .with_context(|| format!("{direction} connections"))?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn process(&mut self, packet: PacketOwned) -> Result<u64> {
-        let ExtractedHeaders {
-            source_ip,
-            dest_port,
-            ..
-        } = packet.headers()?;
+        let headers = packet.headers()?;
+
+        if packet.handshake == Handshake::SynAck {
+            let pending = self.alerts.len();
+            for detector in self.detectors.iter_mut() {
+                detector.observe_answer(&headers, &mut self.alerts);
+            }
+            self.count_alerts(pending);
+            return Ok(self.connection_count);
+        }
 
         self.connection_count += 1;
+        self.window_connections += 1;
         self.captured_bytes += packet.capture_header.caplen as u64;
+        *self
+            .ports
+            .entry((packet.direction, headers.dest_port))
+            .or_default() += 1;
+        if packet.direction == Direction::In {
+            self.sources.insert(headers.source_ip);
+        }
 
+        let key = ConnectionKey::new(packet.direction, &headers, self.key(packet.direction));
         self.connections
-            .entry((source_ip, dest_port))
+            .entry(key)
             .and_modify(|e| *e += 1)
             .or_insert(1);
+        let ts = headers.timestamp();
+        self.spans
+            .entry(key)
+            .and_modify(|(_, last)| *last = ts)
+            .or_insert((ts, ts));
+
+        let pending = self.alerts.len();
+        for detector in self.detectors.iter_mut() {
+            detector.observe(packet.direction, &headers, &mut self.alerts);
+        }
+        self.count_alerts(pending);
+
+        if packet.direction == Direction::In {
+            if let Some(fingerprints) = &mut self.fingerprints {
+                fingerprints.process(&headers);
+            }
+            if let Some(scanners) = &mut self.scanners {
+                scanners.process(&headers);
+            }
+        }
+
+        // recorded last and only logged on failure, so the event log cannot hold back detection
+        if let Some(events) = &mut self.events {
+            if let Err(e) = events.record(packet.direction, &headers) {
+                warn!("event log error: {:#}", e);
+            }
+        }
 
         Ok(self.connection_count)
     }
 
-    #[allow(clippy::type_complexity)]
-    pub fn digest<W: Write>(self, out: &mut W) -> Result<()> {
-        let mut grouped: HashMap<IpAddress, Vec<(u16, i64)>> = HashMap::new();
-        for ((source_ip, dest_port), count) in self.connections {
-            let port_count = (dest_port, count as i64); // i64 for descending sort
-            grouped
-                .entry(source_ip)
-                .and_modify(|counts| counts.push(port_count))
-                .or_insert(vec![port_count]);
+    /// count the alerts raised since the given number were pending, which may not have been taken
+    fn count_alerts(&mut self, from: usize) {
+        for alert in &self.alerts[from..] {
+            *self
+                .alert_counts
+                .entry((alert.kind, alert.port))
+                .or_default() += 1;
         }
+    }
 
-        let mut sorted: Vec<(IpAddress, i64, Vec<(u16, i64)>)> = grouped
-            .into_iter()
-            .map(|(source_ip, mut counts)| {
-                counts.sort_by_key(|t| t.0); // 2. port ascending
-                counts.sort_by_key(|t| -t.1); // 1. count descending
-                let sum = counts.iter().fold(0, |acc, (_, c)| acc + c);
-                (source_ip, sum, counts)
-            })
+    /// alerts raised by detectors since the last call
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.alerts)
+    }
+
+    /// packets dropped by the capture during the current window
+    pub fn record_dropped(&mut self, dropped: u64) {
+        self.dropped += dropped;
+    }
+
+    /// the digest of the current window, which is then closed. a new window starts at `end`
+    pub fn take_digest(&mut self, end: Duration) -> Result<Digest> {
+        let digest = self.snapshot(end)?;
+        self.reset(end);
+        Ok(digest)
+    }
+
+    /// the digest of the current window up to `end`, which stays open
+    pub fn snapshot(&self, end: Duration) -> Result<Digest> {
+        let mut ports: Vec<(Direction, u16, u64)> = self
+            .ports
+            .iter()
+            .map(|(&(direction, port), &count)| (direction, port, count))
+            .collect();
+        ports.sort();
+        Ok(Digest {
+            start: self.window_start,
+            end,
+            periodic: self.interval.is_some(),
+            connections: self.window_connections,
+            ports,
+            sources: self.sources.len() as u64,
+            dropped: self.dropped,
+            flows: self.flows(),
+            sections: self.sections(&self.views)?,
+        })
+    }
+
+    /// discards the counts of the current window, and starts a new one at `start`.
+    /// the totals of the capture are kept
+    pub fn reset(&mut self, start: Duration) {
+        self.window_start = start;
+        self.window_connections = 0;
+        self.dropped = 0;
+        self.connections.clear();
+        self.spans.clear();
+        self.ports.clear();
+        self.sources.clear();
+        self.alert_counts.clear();
+        if let Some(fingerprints) = &mut self.fingerprints {
+            *fingerprints = FingerprintCounter::default();
+        }
+        if let Some(scanners) = &mut self.scanners {
+            *scanners = ScannerCounter::default();
+        }
+    }
+
+    /// the busiest connection keys of the current window, up to `n` of them
+    pub fn top(&self, n: usize) -> Vec<(ConnectionKey, u64)> {
+        let mut top: Vec<(ConnectionKey, u64)> = self
+            .connections
+            .iter()
+            .map(|(&key, &count)| (key, count))
             .collect();
-        sorted.sort_by_key(|t| -t.1); // sum descending
-
-        let mut port_counts = String::new();
-        for (source_ip, sum, counts) in sorted {
-            for (i, (port, count)) in counts.iter().enumerate() {
-                write!(port_counts, "{port}:{count}")?;
-                if i < counts.len() - 1 {
-                    write!(port_counts, " ")?;
+        top.sort_by_key(|&(key, count)| (Reverse(count), key));
+        top.truncate(n);
+        top
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            connection_count: self.connection_count,
+            captured_bytes: self.captured_bytes,
+            window_start: self.window_start,
+            window_connections: self.window_connections,
+            keys: self.connections.len() as u64,
+            sources: self.sources.len() as u64,
+            dropped: self.dropped,
+        }
+    }
+
+    fn flows(&self) -> Vec<Flow> {
+        self.connections
+            .iter()
+            .map(|(&key, &count)| {
+                let (first, last) = self.spans.get(&key).copied().unwrap_or_default();
+                Flow {
+                    key,
+                    count,
+                    first,
+                    last,
                 }
+            })
+            .collect()
+    }
+
+    /// the counts of the current window and the totals of the capture, in a stable order.
+    /// see [Collector::restore]
+    pub fn state(&self) -> State {
+        let mut ports: Vec<(Direction, u16, u64)> = self
+            .ports
+            .iter()
+            .map(|(&(direction, port), &count)| (direction, port, count))
+            .collect();
+        ports.sort();
+        let mut flows = self.flows();
+        flows.sort_by_key(|flow| flow.key);
+        let mut sources: Vec<IpAddress> = self.sources.iter().copied().collect();
+        sources.sort();
+        let fingerprints = self.fingerprints.as_ref().map(|counter| {
+            let mut counts: Vec<(u16, Fingerprint, u64)> = counter
+                .counts
+                .iter()
+                .map(|((port, fingerprint), &count)| (*port, fingerprint.clone(), count))
+                .collect();
+            counts.sort_by_cached_key(|(port, fingerprint, _)| (*port, fingerprint.to_string()));
+            counts
+        });
+        let scanners = self.scanners.as_ref().map(|counter| {
+            let mut counts: Vec<(u16, Classification, u64)> = counter
+                .counts
+                .iter()
+                .map(|(&(port, classification), &count)| (port, classification, count))
+                .collect();
+            counts.sort_by_cached_key(|(port, classification, _)| {
+                (*port, classification.to_string())
+            });
+            counts
+        });
+        let mut alerts: Vec<(AlertKind, Option<u16>, u64)> = self
+            .alert_counts
+            .iter()
+            .map(|(&(kind, port), &count)| (kind, port, count))
+            .collect();
+        alerts.sort_by_cached_key(|(kind, port, _)| (kind.to_string(), *port));
+        State {
+            keys: self
+                .directions
+                .list()
+                .iter()
+                .map(|&direction| (direction, self.key(direction).clone()))
+                .collect(),
+            connection_count: self.connection_count,
+            captured_bytes: self.captured_bytes,
+            window_start: self.window_start,
+            window_connections: self.window_connections,
+            dropped: self.dropped,
+            flows,
+            ports,
+            sources,
+            fingerprints,
+            scanners,
+            alerts,
+        }
+    }
+
+    /// continues the window of a saved state. the state must have been counted with the same
+    /// directions and keys. fingerprints and scanners are only restored if they are still counted
+    pub fn restore(&mut self, state: State) -> Result<()> {
+        let keys: Vec<(Direction, KeySpec)> = self
+            .directions
+            .list()
+            .iter()
+            .map(|&direction| (direction, self.key(direction).clone()))
+            .collect();
+        if state.keys != keys {
+            let fmt = |keys: &[(Direction, KeySpec)]| {
+                let keys: Vec<String> = keys.iter().map(|(d, k)| format!("{d} {k}")).collect();
+                keys.join(", ")
+            };
+            return Err(anyhow!(
+                "state was counted by {}, not {}",
+                fmt(&state.keys),
+                fmt(&keys)
+            ));
+        }
+
+        self.connection_count = state.connection_count;
+        self.captured_bytes = state.captured_bytes;
+        self.window_start = state.window_start;
+        self.window_connections = state.window_connections;
+        self.dropped = state.dropped;
+        self.connections = state.flows.iter().map(|f| (f.key, f.count)).collect();
+        self.spans = state
+            .flows
+            .iter()
+            .map(|f| (f.key, (f.first, f.last)))
+            .collect();
+        self.ports = state
+            .ports
+            .into_iter()
+            .map(|(direction, port, count)| ((direction, port), count))
+            .collect();
+        self.sources = state.sources.into_iter().collect();
+        if let (Some(counter), Some(counts)) = (&mut self.fingerprints, state.fingerprints) {
+            counter.counts = counts
+                .into_iter()
+                .map(|(port, fingerprint, count)| ((port, fingerprint), count))
+                .collect();
+        }
+        if let (Some(counter), Some(counts)) = (&mut self.scanners, state.scanners) {
+            counter.counts = counts
+                .into_iter()
+                .map(|(port, classification, count)| ((port, classification), count))
+                .collect();
+        }
+        self.alert_counts = state
+            .alerts
+            .into_iter()
+            .map(|(kind, port, count)| ((kind, port), count))
+            .collect();
+        Ok(())
+    }
+
+    /// adds everything counted by another collector, which must count the same directions by the
+    /// same keys. the window starts at the earlier of the two. the detectors and event log of the
+    /// other collector are dropped, but alerts it hasn't handed out are kept
+    pub fn merge(&mut self, other: Collector) -> Result<()> {
+        let same = self.directions == other.directions
+            && self.inbound_key == other.inbound_key
+            && self.outbound_key == other.outbound_key;
+        if !same {
+            return Err(anyhow!(
+                "merged collectors must count the same directions by the same keys"
+            ));
+        }
+        self.connection_count += other.connection_count;
+        self.captured_bytes += other.captured_bytes;
+        self.window_start = self.window_start.min(other.window_start);
+        self.window_connections += other.window_connections;
+        self.dropped += other.dropped;
+        self.connections.merge(other.connections);
+        for (key, (first, last)) in other.spans {
+            self.spans
+                .entry(key)
+                .and_modify(|span| *span = (span.0.min(first), span.1.max(last)))
+                .or_insert((first, last));
+        }
+        self.ports.merge(other.ports);
+        self.sources.merge(other.sources);
+        self.fingerprints.merge(other.fingerprints);
+        self.scanners.merge(other.scanners);
+        self.alerts.extend(other.alerts);
+        self.alert_counts.merge(other.alert_counts);
+        Ok(())
+    }
+
+    /// counts of each distinct connection key
+    pub fn records(&self) -> impl Iterator<Item = (&ConnectionKey, &u64)> {
+        self.connections.iter()
+    }
+
+    /// one table per view, or a single table grouped by the key spec if there are no views.
+    /// tables are titled when there is more than one, and repeated for each monitored direction.
+    pub fn sections(&self, views: &[GroupBy]) -> Result<Vec<Section>> {
+        let mut sections = Vec::new();
+        for &direction in self.directions.list() {
+            let default_view = [GroupBy::from(self.key(direction))];
+            let views = if views.is_empty() {
+                &default_view[..]
+            } else {
+                views
+            };
+
+            for view in views {
+                let records = self.records().filter(|(k, _)| k.direction == direction);
+                let table = view.aggregate(records)?;
+                let title = match (self.directions, views.len()) {
+                    (Directions::Both, 1) => Some(direction.to_string()),
+                    (Directions::Both, _) => Some(format!("{direction} {view}")),
+                    (_, 1) => None,
+                    _ => Some(view.to_string()),
+                };
+                sections.push(Section::grouped(title, view, table));
+            }
+        }
+
+        if let Some(fingerprints) = &self.fingerprints {
+            sections.push(Section::new(
+                Some("fingerprints".into()),
+                &["dport", "fingerprint"],
+                fingerprints.table(),
+            ));
+        }
+
+        if let Some(scanners) = &self.scanners {
+            sections.push(Section::new(
+                Some("scanners".into()),
+                &["dport", "class"],
+                scanners.table(),
+            ));
+        }
+
+        if !self.alert_counts.is_empty() {
+            let mut grouped: HashMap<String, HashMap<String, u64>> = HashMap::new();
+            for ((kind, port), count) in &self.alert_counts {
+                let port = port.map(|p| p.to_string()).unwrap_or_default();
+                *grouped
+                    .entry(kind.to_string())
+                    .or_default()
+                    .entry(port)
+                    .or_default() += count;
             }
-            writeln!(out, "{source_ip}:{sum} {port_counts}")?;
-            port_counts.clear();
+            sections.push(Section::new(
+                Some("alerts".into()),
+                &["kind", "dport"],
+                Table::from_grouped(grouped),
+            ));
         }
 
         debug!("captured bytes: {}", self.captured_bytes);
 
+        Ok(sections)
+    }
+
+    /// writes the sections as text
+    pub fn digest<W: Write>(&self, views: &[GroupBy], out: &mut W) -> Result<()> {
+        for section in self.sections(views)? {
+            section.write(out)?;
+        }
         Ok(())
     }
 }
 
+/// totals of a collector, and of its current window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub connection_count: u64,
+    pub captured_bytes: u64,
+    pub window_start: Duration,
+    pub window_connections: u64,
+    /// distinct connection keys in the window
+    pub keys: u64,
+    /// distinct sources of inbound connections in the window
+    pub sources: u64,
+    /// packets dropped by the capture during the window
+    pub dropped: u64,
+}
+
+/// everything a collector has counted, without its configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    /// key of each monitored direction
+    pub keys: Vec<(Direction, KeySpec)>,
+    pub connection_count: u64,
+    pub captured_bytes: u64,
+    pub window_start: Duration,
+    pub window_connections: u64,
+    pub dropped: u64,
+    pub flows: Vec<Flow>,
+    pub ports: Vec<(Direction, u16, u64)>,
+    pub sources: Vec<IpAddress>,
+    /// connections per port and SYN fingerprint, if counted
+    pub fingerprints: Option<Vec<(u16, Fingerprint, u64)>>,
+    /// connections per port and scanner classification, if counted
+    pub scanners: Option<Vec<(u16, Classification, u64)>>,
+    pub alerts: Vec<(AlertKind, Option<u16>, u64)>,
+}
+
+/// wall clock time since the unix epoch
+pub fn unix_now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::time::Duration;
+
+    use etherparse::PacketBuilder;
+    use libc::timeval;
+    use pcap::PacketHeader;
+    use proptest::prelude::*;
 
-    use crate::collector::Collector;
+    use crate::alert::AlertKind;
+    use crate::capture::{Direction, Directions, Handshake, PacketOwned};
+    use crate::collector::{Collector, ConnectionKey, KeySpec, State};
+    use crate::portscan::PortScanDetector;
     use crate::sensitive::IpAddress;
 
+    fn key(source_ip: Option<IpAddress>, dest_ip: Option<IpAddress>, port: u16) -> ConnectionKey {
+        ConnectionKey {
+            direction: Direction::In,
+            source_ip,
+            dest_ip,
+            dest_port: Some(port),
+        }
+    }
+
     #[test]
     fn test_digest_grouped_sorted() {
         let ip1 = IpAddress::V6(1u128.swap_bytes().to_ne_bytes());
@@ -88,16 +784,16 @@ mod tests {
 
         let collector = Collector {
             connections: HashMap::from([
-                ((ip1, 123), 1),
-                ((ip1, 234), 1),
-                ((ip2, 345), 1),
-                ((ip2, 456), 2),
+                (key(Some(ip1), None, 123), 1),
+                (key(Some(ip1), None, 234), 1),
+                (key(Some(ip2), None, 345), 1),
+                (key(Some(ip2), None, 456), 2),
             ]),
             ..Default::default()
         };
 
         let mut out = Vec::new();
-        collector.digest(&mut out).unwrap();
+        collector.digest(&[], &mut out).unwrap();
 
         assert_eq!(
             String::from_utf8(out).unwrap(),
@@ -107,4 +803,216 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_digest_destination_key() {
+        let src = IpAddress::V4([10, 0, 0, 1]);
+        let dst1 = IpAddress::V4([10, 0, 0, 2]);
+        let dst2 = IpAddress::V4([10, 0, 0, 3]);
+
+        let collector = Collector {
+            inbound_key: "dst,src,dport".parse().unwrap(),
+            connections: HashMap::from([
+                (key(Some(src), Some(dst1), 22), 1),
+                (key(Some(src), Some(dst2), 22), 2),
+                (key(Some(src), Some(dst2), 80), 1),
+            ]),
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        collector.digest(&[], &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "{dst2}:3 {src}/22:2 {src}/80:1\n\
+                 {dst1}:1 {src}/22:1\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_key_spec_parse() {
+        assert_eq!("src,dport".parse::<KeySpec>().unwrap(), KeySpec::default());
+        assert!("src,src".parse::<KeySpec>().is_err());
+        assert!("sport".parse::<KeySpec>().is_err());
+    }
+
+    #[test]
+    fn test_digest_both_directions() {
+        let remote = IpAddress::V4([192, 0, 2, 1]);
+
+        let mut collector = Collector::new(Directions::Both, None);
+        collector.connections = HashMap::from([
+            (key(Some(remote), None, 22), 2),
+            (
+                ConnectionKey {
+                    direction: Direction::Out,
+                    source_ip: None,
+                    dest_ip: Some(remote),
+                    dest_port: Some(443),
+                },
+                1,
+            ),
+        ]);
+
+        let mut out = Vec::new();
+        collector.digest(&[], &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "# inbound\n\
+                 {remote}:2 22:2\n\
+                 # outbound\n\
+                 {remote}:1 443:1\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_take_digest_closes_window() {
+        let remote = IpAddress::V4([192, 0, 2, 1]);
+        let mut collector = Collector::default().interval(Some(Duration::from_secs(60)));
+        collector.connections = HashMap::from([(key(Some(remote), None, 22), 2)]);
+        collector.record_dropped(3);
+
+        let end = collector.window_start + Duration::from_secs(60);
+        let digest = collector.take_digest(end).unwrap();
+        assert!(digest.periodic);
+        assert_eq!(digest.dropped, 3);
+        assert_eq!(digest.sections[0].table.rows[0].total, 2);
+
+        let next = collector
+            .take_digest(end + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(next.start, end);
+        assert_eq!(next.dropped, 0);
+        assert!(next.sections[0].table.rows.is_empty());
+    }
+
+    #[test]
+    fn test_alerts_counted_once_until_taken() {
+        let mut collector = Collector::new(Directions::In, None).detector(PortScanDetector::new(
+            Duration::from_secs(10),
+            Some(1),
+            None,
+        ));
+        for (index, port) in [22, 80, 443].into_iter().enumerate() {
+            collector.process(packet(index, 1, port, true)).unwrap();
+        }
+        assert_eq!(
+            collector.state().alerts,
+            vec![(AlertKind::VerticalScan, None, 1)]
+        );
+        assert_eq!(collector.take_alerts().len(), 1);
+    }
+
+    /// a SYN from one of a few sources to one of a few ports, one second after the previous one
+    fn packet(index: usize, source: u8, port: u16, inbound: bool) -> PacketOwned {
+        let mut data = Vec::new();
+        PacketBuilder::ethernet2([0; 6], [0; 6])
+            .ipv4([192, 0, 2, source], [198, 51, 100, 1], 64)
+            .tcp(40000, port, index as u32, 64240)
+            .syn()
+            .write(&mut data, &[])
+            .unwrap();
+        let header = PacketHeader {
+            ts: timeval {
+                tv_sec: 1697723000 + index as libc::time_t,
+                tv_usec: 0,
+            },
+            caplen: data.len() as u32,
+            len: data.len() as u32,
+        };
+        let direction = if inbound {
+            Direction::In
+        } else {
+            Direction::Out
+        };
+        PacketOwned::new(header, direction, Handshake::Syn, &data)
+    }
+
+    type Connection = (u8, u16, bool);
+
+    /// connections, and the shard each of their sources is assigned to
+    type Sharded = ([usize; 4], Vec<Connection>);
+
+    fn connections() -> impl Strategy<Value = Sharded> {
+        let port = prop::sample::select(vec![22u16, 80, 443]);
+        (
+            prop::array::uniform4(0usize..3),
+            prop::collection::vec((0u8..4, port, any::<bool>()), 0..40),
+        )
+    }
+
+    /// counts the connections of the sources assigned to a shard, or all of them.
+    /// port scans are detected per source, so a shard sees every connection a scan is made of
+    fn collect((shards, connections): &Sharded, shard: Option<usize>) -> Collector {
+        let mut collector = Collector::new(Directions::Both, None)
+            .fingerprints(true)
+            .scanners(true)
+            .detector(PortScanDetector::new(
+                Duration::from_secs(10),
+                Some(1),
+                None,
+            ));
+        for (index, &(source, port, inbound)) in connections.iter().enumerate() {
+            if shard.map_or(true, |shard| shard == shards[source as usize]) {
+                collector
+                    .process(packet(index, source, port, inbound))
+                    .unwrap();
+            }
+        }
+        collector
+    }
+
+    /// the state without the start of the window, which is the time the collector was created
+    fn counted(collector: &Collector) -> State {
+        State {
+            window_start: Duration::ZERO,
+            ..collector.state()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_merge_matches_single_collector(connections in connections()) {
+            let mut merged = collect(&connections, Some(0));
+            merged.merge(collect(&connections, Some(1))).unwrap();
+            merged.merge(collect(&connections, Some(2))).unwrap();
+            let mut single = collect(&connections, None);
+            prop_assert_eq!(counted(&merged), counted(&single));
+            prop_assert_eq!(merged.take_alerts().len(), single.take_alerts().len());
+        }
+
+        #[test]
+        fn test_merge_associative_commutative(connections in connections()) {
+            let shard = |i| collect(&connections, Some(i));
+            let mut left = shard(0);
+            left.merge(shard(1)).unwrap();
+            left.merge(shard(2)).unwrap();
+            let mut right = shard(1);
+            right.merge(shard(2)).unwrap();
+            let mut right_first = shard(0);
+            right_first.merge(right).unwrap();
+            prop_assert_eq!(counted(&left), counted(&right_first));
+
+            let mut reversed = shard(2);
+            reversed.merge(shard(1)).unwrap();
+            reversed.merge(shard(0)).unwrap();
+            prop_assert_eq!(counted(&left), counted(&reversed));
+        }
+    }
+
+    #[test]
+    fn test_merge_different_keys() {
+        let mut collector = Collector::default();
+        let other = Collector::new(Directions::In, Some("dport".parse().unwrap()));
+        let error = collector.merge(other).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("same directions by the same keys"));
+    }
 }
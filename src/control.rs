@@ -0,0 +1,384 @@
+use std::fs::{self, DirBuilder};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener as StdUnixListener, UnixStream as StdUnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use log::{debug, warn};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+
+use crate::collector::Collector;
+
+/// keys listed by `top` without a count
+pub const DEFAULT_TOP: usize = 10;
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// longest request line accepted
+const MAX_REQUEST: u64 = 1024;
+
+/// a command sent to the control socket of a running capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// the digest of the current window so far
+    Snapshot,
+    /// the busiest connection keys of the current window
+    Top(usize),
+    /// discard the counts of the current window
+    Reset,
+    /// totals of the capture and of the current window
+    Stats,
+}
+
+impl FromStr for Request {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words[..] {
+            ["snapshot"] => Ok(Request::Snapshot),
+            ["top"] => Ok(Request::Top(DEFAULT_TOP)),
+            ["top", n] => match n.parse() {
+                Ok(n) if n > 0 => Ok(Request::Top(n)),
+                _ => Err(anyhow!("invalid count: {n} (expected a positive number)")),
+            },
+            ["reset"] => Ok(Request::Reset),
+            ["stats"] => Ok(Request::Stats),
+            _ => Err(anyhow!(
+                "unknown command: {s} (expected snapshot, top [<n>], reset or stats)"
+            )),
+        }
+    }
+}
+
+/// the response to a request, as text
+pub fn answer(collector: &mut Collector, request: Request, now: Duration) -> Result<String> {
+    let mut out = Vec::new();
+    match request {
+        Request::Snapshot => {
+            let digest = collector.snapshot(now)?;
+            // headed by its window even if the capture isn't periodic
+            writeln!(
+                out,
+                "# window {} {}",
+                digest.start.as_secs(),
+                digest.end.as_secs()
+            )?;
+            for section in &digest.sections {
+                section.write(&mut out)?;
+            }
+        }
+        Request::Top(n) => {
+            for (key, count) in collector.top(n) {
+                let labels: Vec<String> = collector
+                    .key(key.direction)
+                    .dimensions()
+                    .iter()
+                    .filter_map(|dimension| dimension.label(&key))
+                    .collect();
+                writeln!(
+                    out,
+                    "{} {}:{count}",
+                    key.direction.short(),
+                    labels.join("/")
+                )?;
+            }
+        }
+        Request::Reset => {
+            let stats = collector.stats();
+            collector.reset(now);
+            writeln!(
+                out,
+                "reset window of {} connections since {}",
+                stats.window_connections,
+                stats.window_start.as_secs()
+            )?;
+        }
+        Request::Stats => {
+            let stats = collector.stats();
+            for (name, value) in [
+                ("window_start", stats.window_start.as_secs()),
+                ("window_connections", stats.window_connections),
+                ("keys", stats.keys),
+                ("sources", stats.sources),
+                ("dropped", stats.dropped),
+                ("connections", stats.connection_count),
+                ("captured_bytes", stats.captured_bytes),
+            ] {
+                writeln!(out, "{name} {value}")?;
+            }
+        }
+    }
+    Ok(String::from_utf8(out)?)
+}
+
+/// a request received on the control socket, waiting to be answered from the collector
+#[derive(Debug)]
+pub struct Query {
+    pub request: Request,
+    reply: oneshot::Sender<String>,
+}
+
+impl Query {
+    /// answers the client, with `error: <reason>` if the request failed
+    pub fn answer(self, collector: &mut Collector, now: Duration) {
+        let response =
+            answer(collector, self.request, now).unwrap_or_else(|e| format!("error: {e:#}\n"));
+        // the client may have gone
+        let _ = self.reply.send(response);
+    }
+}
+
+/// a Unix domain socket which only the owner of the process can connect to. clients send one
+/// request line and read the response until the socket is closed
+#[derive(Debug)]
+pub struct ControlSocket {
+    listener: StdUnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// binds the socket, replacing one left by a previous run
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                bail!("{} exists and is not a socket", path.display());
+            }
+            if StdUnixStream::connect(path).is_ok() {
+                bail!("{} is in use by another process", path.display());
+            }
+            fs::remove_file(path).with_context(|| format!("remove {}", path.display()))?;
+        }
+        // no one else can enter the directory, so no one can connect before the socket is private
+        let mut private = path.as_os_str().to_os_string();
+        private.push(format!(".{}.tmp", std::process::id()));
+        let private = PathBuf::from(private);
+        DirBuilder::new()
+            .mode(0o700)
+            .create(&private)
+            .with_context(|| format!("create {}", private.display()))?;
+        let bound = bind_within(&private, path);
+        let _ = fs::remove_dir_all(&private);
+        let listener = bound?;
+        listener.set_nonblocking(true)?;
+        Ok(ControlSocket {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// accepts clients in the background on the current tokio runtime, passing on their requests
+    pub fn serve(&self) -> Result<mpsc::Receiver<Query>> {
+        let listener = UnixListener::from_std(self.listener.try_clone()?)?;
+        let (queries, received) = mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let queries = queries.clone();
+                        tokio::spawn(async move {
+                            match time::timeout(TIMEOUT, respond(stream, queries)).await {
+                                Ok(Ok(())) => (),
+                                Ok(Err(e)) => warn!("control client error: {e:#}"),
+                                Err(_) => warn!("control client timed out"),
+                            }
+                        });
+                    }
+                    Err(e) => warn!("control accept error: {e}"),
+                }
+            }
+        });
+        Ok(received)
+    }
+}
+
+/// binds a socket in a private directory, and moves it to its path once only the owner can use it
+fn bind_within(private: &Path, path: &Path) -> Result<StdUnixListener> {
+    let socket = private.join("socket");
+    let listener =
+        StdUnixListener::bind(&socket).with_context(|| format!("bind {}", path.display()))?;
+    fs::set_permissions(&socket, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&socket, path).with_context(|| format!("rename to {}", path.display()))?;
+    Ok(listener)
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            debug!("remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+async fn respond(mut stream: UnixStream, queries: mpsc::Sender<Query>) -> Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut line = String::new();
+    BufReader::new(reader)
+        .take(MAX_REQUEST)
+        .read_line(&mut line)
+        .await?;
+    let response = match line.trim().parse() {
+        Ok(request) => {
+            let (reply, response) = oneshot::channel();
+            queries
+                .send(Query { request, reply })
+                .await
+                .map_err(|_| anyhow!("capture stopped"))?;
+            response.await.map_err(|_| anyhow!("capture stopped"))?
+        }
+        Err(e) => format!("error: {e:#}\n"),
+    };
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// sends a request to the control socket of a running capture, and returns the response
+pub fn request<P: AsRef<Path>>(path: P, request: &str) -> Result<String> {
+    let path = path.as_ref();
+    let mut stream =
+        StdUnixStream::connect(path).with_context(|| format!("connect to {}", path.display()))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    writeln!(stream, "{request}").context("send request")?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .context("read response")?;
+    match response.strip_prefix("error: ") {
+        Some(error) => Err(anyhow!("{}", error.trim_end())),
+        None => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+    use std::time::Duration;
+
+    use tokio::runtime;
+
+    use crate::capture::{Direction, Directions};
+    use crate::collector::{Collector, ConnectionKey, Flow, KeySpec, State};
+    use crate::control::{answer, request, ControlSocket, Request};
+    use crate::sensitive::IpAddress;
+
+    fn collector() -> Collector {
+        let flow = |source: [u8; 4], port, count| Flow {
+            key: ConnectionKey {
+                direction: Direction::In,
+                source_ip: Some(IpAddress::V4(source)),
+                dest_ip: None,
+                dest_port: Some(port),
+            },
+            count,
+            first: Duration::from_secs(1697723001),
+            last: Duration::from_secs(1697723002),
+        };
+        let mut collector = Collector::new(Directions::In, None);
+        collector
+            .restore(State {
+                keys: vec![(Direction::In, KeySpec::default_for(Direction::In))],
+                connection_count: 20,
+                captured_bytes: 1200,
+                window_start: Duration::from_secs(1697723000),
+                window_connections: 6,
+                flows: vec![flow([192, 0, 2, 1], 22, 4), flow([192, 0, 2, 2], 80, 2)],
+                ports: vec![(Direction::In, 22, 4), (Direction::In, 80, 2)],
+                sources: vec![IpAddress::V4([192, 0, 2, 1]), IpAddress::V4([192, 0, 2, 2])],
+                ..Default::default()
+            })
+            .unwrap();
+        collector
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!("top".parse::<Request>().unwrap(), Request::Top(10));
+        assert_eq!(" top  3\n".parse::<Request>().unwrap(), Request::Top(3));
+        assert_eq!("stats".parse::<Request>().unwrap(), Request::Stats);
+        assert!("top 0".parse::<Request>().is_err());
+        assert_eq!(
+            "flush".parse::<Request>().unwrap_err().to_string(),
+            "unknown command: flush (expected snapshot, top [<n>], reset or stats)"
+        );
+    }
+
+    #[test]
+    fn test_answer() {
+        let mut collector = collector();
+        let now = Duration::from_secs(1697723030);
+        let (source1, source2) = (IpAddress::V4([192, 0, 2, 1]), IpAddress::V4([192, 0, 2, 2]));
+
+        let top = answer(&mut collector, Request::Top(1), now).unwrap();
+        assert_eq!(top, format!("in {source1}/22:4\n"));
+        let snapshot = answer(&mut collector, Request::Snapshot, now).unwrap();
+        assert_eq!(
+            snapshot,
+            format!("# window 1697723000 1697723030\n{source1}:4 22:4\n{source2}:2 80:2\n")
+        );
+
+        let reset = answer(&mut collector, Request::Reset, now).unwrap();
+        assert_eq!(reset, "reset window of 6 connections since 1697723000\n");
+        let stats = answer(&mut collector, Request::Stats, now).unwrap();
+        assert_eq!(
+            stats,
+            "window_start 1697723030\nwindow_connections 0\nkeys 0\nsources 0\ndropped 0\n\
+             connections 20\ncaptured_bytes 1200\n"
+        );
+        assert_eq!(answer(&mut collector, Request::Top(5), now).unwrap(), "");
+    }
+
+    #[test]
+    fn test_control_socket() {
+        let path = std::env::temp_dir().join(format!("pulso-control-{}.sock", std::process::id()));
+        let socket = ControlSocket::bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let private = format!("{}.{}.tmp", path.display(), std::process::id());
+        assert!(!std::path::Path::new(&private).exists());
+        assert!(ControlSocket::bind(&path).is_err());
+
+        let client_path = path.clone();
+        let client = thread::spawn(move || {
+            let stats = request(&client_path, "stats").unwrap();
+            let reset = request(&client_path, "reset").unwrap();
+            let error = request(&client_path, "top x").unwrap_err();
+            (stats, reset, error.to_string())
+        });
+
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut collector = collector();
+        runtime.block_on(async {
+            let mut queries = socket.serve().unwrap();
+            for _ in 0..2 {
+                let query = queries.recv().await.unwrap();
+                query.answer(&mut collector, Duration::from_secs(1697723030));
+            }
+            // the invalid request is answered without reaching the collector
+            while !client.is_finished() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        let (stats, reset, error) = client.join().unwrap();
+        assert!(stats.starts_with("window_start 1697723000\nwindow_connections 6\n"));
+        assert_eq!(reset, "reset window of 6 connections since 1697723000\n");
+        assert_eq!(error, "invalid count: x (expected a positive number)");
+        assert_eq!(collector.stats().window_connections, 0);
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,350 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+use serde_json::{json, Value};
+
+use crate::aggregate::Digest;
+use crate::parse::DigestFile;
+
+/// sections of a text digest which don't count connections
+const OTHER_SECTIONS: [&str; 3] = ["fingerprints", "scanners", "alerts"];
+
+/// how a diff is written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for DiffFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(DiffFormat::Text),
+            "json" => Ok(DiffFormat::Json),
+            other => Err(anyhow!("unknown format: {other} (expected text or json)")),
+        }
+    }
+}
+
+/// connections per source and per destination port, the parts of a digest which are compared
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub sources: HashMap<String, u64>,
+    pub ports: HashMap<u16, u64>,
+}
+
+impl Summary {
+    /// counts of a digest file
+    pub fn of(file: &DigestFile) -> Self {
+        match file {
+            DigestFile::Text(digest) => Self::of_text(digest),
+            DigestFile::Csv(digest) => {
+                let mut summary = Summary::default();
+                for ((source, port), &count) in &digest.counts {
+                    summary.add(Some(source), *port, count);
+                }
+                summary
+            }
+        }
+    }
+
+    /// text digests don't name their columns, so labels which are ports are counted as ports, and
+    /// the first other label of an inbound row as its source. only the first table of each
+    /// direction is read, as further tables are other views of the same connections. untitled
+    /// tables are taken to be inbound
+    fn of_text(digest: &Digest) -> Self {
+        let mut summary = Summary::default();
+        let mut read = HashSet::new();
+        for section in &digest.sections {
+            let title = section.title.as_deref().unwrap_or_default();
+            let inbound = !title.starts_with("outbound");
+            if OTHER_SECTIONS.contains(&title) || !read.insert(inbound) {
+                continue;
+            }
+            for row in &section.table.rows {
+                let items = match row.items.is_empty() {
+                    true => vec![(row.group.clone(), row.total)],
+                    false => row
+                        .items
+                        .iter()
+                        .map(|(item, count)| (format!("{}/{item}", row.group), *count))
+                        .collect(),
+                };
+                for (labels, count) in items {
+                    let (ports, others): (Vec<&str>, Vec<&str>) = labels
+                        .split('/')
+                        .partition(|label| label.parse::<u16>().is_ok());
+                    let source = others.first().copied().filter(|_| inbound);
+                    let port = ports.first().and_then(|port| port.parse().ok());
+                    summary.add(source, port, count);
+                }
+            }
+        }
+        summary
+    }
+
+    fn add(&mut self, source: Option<&str>, port: Option<u16>, count: u64) {
+        if let Some(source) = source.filter(|s| !s.is_empty()) {
+            *self.sources.entry(source.to_string()).or_default() += count;
+        }
+        if let Some(port) = port {
+            *self.ports.entry(port).or_default() += count;
+        }
+    }
+}
+
+/// connections to a port before and after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortChange {
+    pub port: u16,
+    pub before: u64,
+    pub after: u64,
+}
+
+impl PortChange {
+    pub fn delta(&self) -> i64 {
+        self.after as i64 - self.before as i64
+    }
+
+    /// change as a fraction of the count before, unless the port is new
+    pub fn relative(&self) -> Option<f64> {
+        (self.before > 0).then(|| self.delta() as f64 / self.before as f64)
+    }
+}
+
+/// what changed between two digests, with the largest changes first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diff {
+    /// sources only seen after, with their connections
+    pub new_sources: Vec<(String, u64)>,
+    /// sources only seen before, with their connections
+    pub vanished_sources: Vec<(String, u64)>,
+    /// ports whose connections changed
+    pub ports: Vec<PortChange>,
+}
+
+fn only_in(a: &Summary, b: &Summary) -> Vec<(String, u64)> {
+    let mut sources: Vec<(String, u64)> = a
+        .sources
+        .iter()
+        .filter(|(source, _)| !b.sources.contains_key(*source))
+        .map(|(source, &count)| (source.clone(), count))
+        .collect();
+    sources.sort_by(|x, y| (Reverse(x.1), &x.0).cmp(&(Reverse(y.1), &y.0)));
+    sources
+}
+
+impl Diff {
+    pub fn new(before: &Summary, after: &Summary) -> Self {
+        let ports: HashSet<u16> = before
+            .ports
+            .keys()
+            .chain(after.ports.keys())
+            .copied()
+            .collect();
+        let mut ports: Vec<PortChange> = ports
+            .into_iter()
+            .map(|port| PortChange {
+                port,
+                before: before.ports.get(&port).copied().unwrap_or_default(),
+                after: after.ports.get(&port).copied().unwrap_or_default(),
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+        ports.sort_by_key(|change| (Reverse(change.delta().unsigned_abs()), change.port));
+        Diff {
+            new_sources: only_in(after, before),
+            vanished_sources: only_in(before, after),
+            ports,
+        }
+    }
+
+    /// sections of `source:count` lines, and `port:delta before->after relative` lines
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        for (title, sources) in [
+            ("new sources", &self.new_sources),
+            ("vanished sources", &self.vanished_sources),
+        ] {
+            writeln!(out, "# {title}")?;
+            for (source, count) in sources {
+                writeln!(out, "{source}:{count}")?;
+            }
+        }
+        writeln!(out, "# ports")?;
+        for change in &self.ports {
+            let relative = match change.relative() {
+                Some(relative) => format!("{:+.1}%", relative * 100.0),
+                None => "new".to_string(),
+            };
+            writeln!(
+                out,
+                "{}:{:+} {}->{} {relative}",
+                change.port,
+                change.delta(),
+                change.before,
+                change.after
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Value {
+        let sources = |sources: &[(String, u64)]| -> Vec<Value> {
+            sources
+                .iter()
+                .map(|(source, count)| json!({ "source": source, "count": count }))
+                .collect()
+        };
+        let ports: Vec<Value> = self
+            .ports
+            .iter()
+            .map(|change| {
+                json!({
+                    "port": change.port,
+                    "before": change.before,
+                    "after": change.after,
+                    "delta": change.delta(),
+                    "relative": change.relative(),
+                })
+            })
+            .collect();
+        json!({
+            "new_sources": sources(&self.new_sources),
+            "vanished_sources": sources(&self.vanished_sources),
+            "ports": ports,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::aggregate::{Digest, DigestFormat, GroupBy, Section};
+    use crate::capture::Direction;
+    use crate::collector::{ConnectionKey, Flow};
+    use crate::diff::{Diff, PortChange, Summary};
+    use crate::output::{Sink, Text};
+    use crate::parse::DigestFile;
+    use crate::sensitive::IpAddress;
+
+    fn summary(input: &str) -> Summary {
+        Summary::of(&DigestFile::parse(input).unwrap())
+    }
+
+    /// a CSV digest of one window with rows of source, port and count
+    fn csv(rows: &[(&str, u16, u64)]) -> String {
+        let mut out = "window_start,window_end,source,port,count\n".to_string();
+        for (source, port, count) in rows {
+            out += &format!(
+                "2023-10-19T13:00:00.000000Z,2023-10-19T14:00:00.000000Z,{source},{port},{count}\n"
+            );
+        }
+        out
+    }
+
+    #[test]
+    fn test_summary() {
+        let csv = summary(&csv(&[("aa", 22, 3), ("aa", 80, 2), ("", 80, 1)]));
+        assert_eq!(csv.sources.len(), 1);
+        assert_eq!(csv.sources["aa"], 5);
+        assert_eq!(csv.ports[&80], 3);
+
+        let text = summary(
+            "# inbound\naa:5 22:3 80:2\nbb:1 22:1\n\
+             # outbound\ncc:4 443:4\n\
+             # fingerprints\n22:1 64240_2-4-8-1-3_1460_7:1\n",
+        );
+        assert_eq!(text.sources.len(), 2);
+        assert_eq!((text.sources["aa"], text.sources["bb"]), (5, 1));
+        assert_eq!(text.ports.get(&22), Some(&4));
+        assert_eq!(text.ports.get(&443), Some(&4));
+    }
+
+    #[test]
+    fn test_summary_multiple_views() {
+        let key = |source: u8, port: u16| ConnectionKey {
+            direction: Direction::In,
+            source_ip: Some(IpAddress::V4([192, 0, 2, source])),
+            dest_ip: None,
+            dest_port: Some(port),
+        };
+        let flow = |key: ConnectionKey, count: u64| Flow {
+            key,
+            count,
+            first: Duration::ZERO,
+            last: Duration::ZERO,
+        };
+        let flows = vec![flow(key(1, 22), 3), flow(key(2, 22), 1)];
+        let view = |group_by: &str| {
+            let table = group_by
+                .parse::<GroupBy>()
+                .unwrap()
+                .aggregate(flows.iter().map(|f| (&f.key, &f.count)))
+                .unwrap();
+            Section::new(Some(group_by.to_string()), &[], table)
+        };
+        let digest = Digest {
+            sections: vec![view("dport,src"), view("src,dport")],
+            flows: flows.clone(),
+            ..Default::default()
+        };
+        for format in [DigestFormat::Text, DigestFormat::Csv] {
+            let mut out = Vec::new();
+            Text::new(&mut out)
+                .format(format, true)
+                .digest(&digest)
+                .unwrap();
+
+            let summary = summary(&String::from_utf8(out).unwrap());
+            assert_eq!(summary.ports[&22], 4, "{format:?}");
+            assert_eq!(summary.sources.values().sum::<u64>(), 4, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = summary(&csv(&[("aa", 22, 10), ("aa", 80, 2), ("bb", 22, 3)]));
+        let after = summary(&csv(&[("aa", 22, 7), ("cc", 5432, 40), ("dd", 80, 1)]));
+        let diff = Diff::new(&before, &after);
+        assert_eq!(
+            diff.new_sources,
+            [("cc".to_string(), 40), ("dd".to_string(), 1)]
+        );
+        assert_eq!(diff.vanished_sources, [("bb".to_string(), 3)]);
+
+        let mut out = Vec::new();
+        diff.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# new sources\ncc:40\ndd:1\n# vanished sources\nbb:3\n\
+             # ports\n5432:+40 0->40 new\n22:-6 13->7 -46.2%\n80:-1 2->1 -50.0%\n"
+        );
+
+        let json = diff.to_json();
+        assert_eq!(
+            json["ports"][1],
+            json!({ "port": 22, "before": 13, "after": 7, "delta": -6, "relative": -6.0 / 13.0 })
+        );
+        assert_eq!(json["ports"][0]["relative"], json!(null));
+        assert_eq!(
+            json["vanished_sources"],
+            json!([{ "source": "bb", "count": 3 }])
+        );
+
+        let change = PortChange {
+            port: 22,
+            before: 0,
+            after: 0,
+        };
+        assert_eq!((change.delta(), change.relative()), (0, None));
+    }
+}
@@ -0,0 +1,168 @@
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{Context, Error, Result};
+
+use crate::capture::{Direction, ExtractedHeaders};
+use crate::output::{Syslog, SYSLOG_SOCKET};
+
+/// where connection events are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventTarget {
+    /// the local syslog socket
+    Syslog,
+    /// a file, appended to
+    File(PathBuf),
+}
+
+impl FromStr for EventTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "syslog" => EventTarget::Syslog,
+            path => EventTarget::File(path.into()),
+        })
+    }
+}
+
+enum Output {
+    Syslog(Syslog),
+    File(LineWriter<File>),
+}
+
+/// one line per counted connection, for tools like fail2ban which match log lines.
+///
+/// `<unix time> connection <direction> src=<address> dport=<port> device=<name>`
+///
+/// the timestamp is that of the capture, and can be matched with fail2ban's `{EPOCH}` date pattern.
+/// with the "privacy" feature, sources are pseudonyms unless real addresses are requested.
+pub struct EventLog {
+    output: Output,
+    device: String,
+    addresses: bool,
+    /// one in this many connections is written
+    sample: u64,
+    seen: u64,
+}
+
+impl EventLog {
+    pub fn open(target: &EventTarget, device: &str, sample: u64) -> Result<Self> {
+        let output = match target {
+            EventTarget::Syslog => Output::Syslog(Syslog::connect(SYSLOG_SOCKET)?),
+            EventTarget::File(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("open event file {}", path.display()))?;
+                Output::File(LineWriter::new(file))
+            }
+        };
+        Ok(EventLog {
+            output,
+            device: device.to_string(),
+            addresses: cfg!(not(feature = "privacy")),
+            sample: sample.max(1),
+            seen: 0,
+        })
+    }
+
+    /// whether lines carry the real source address, in the clear
+    pub fn addresses(mut self, enabled: bool) -> Self {
+        self.addresses = enabled;
+        self
+    }
+
+    pub fn format(&self, direction: Direction, headers: &ExtractedHeaders) -> String {
+        let ts = headers.timestamp();
+        let source = if self.addresses {
+            headers.source_ip.reveal().to_string()
+        } else {
+            headers.source_ip.to_string()
+        };
+        format!(
+            "{}.{:06} connection {direction} src={source} dport={} device={}",
+            ts.as_secs(),
+            ts.subsec_micros(),
+            headers.dest_port,
+            self.device
+        )
+    }
+
+    pub fn record(&mut self, direction: Direction, headers: &ExtractedHeaders) -> Result<()> {
+        self.seen += 1;
+        if (self.seen - 1) % self.sample != 0 {
+            return Ok(());
+        }
+        let line = self.format(direction, headers);
+        match &mut self.output {
+            Output::Syslog(syslog) => {
+                syslog.send(6, headers.timestamp(), "connection", &[], &line)?
+            }
+            Output::File(file) => writeln!(file, "{line}").context("write event file")?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use libc::timeval;
+
+    use crate::capture::{Direction, ExtractedHeaders};
+    use crate::events::{EventLog, EventTarget};
+    use crate::sensitive::IpAddress;
+
+    fn headers(secs: i64, dest_port: u16) -> ExtractedHeaders {
+        ExtractedHeaders {
+            source_ip: IpAddress::V4([192, 0, 2, 1]),
+            source_port: 40000,
+            dest_ip: IpAddress::V4([10, 0, 0, 1]),
+            dest_port,
+            capture_ts: timeval {
+                tv_sec: secs,
+                tv_usec: 2500,
+            },
+            syn: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_sampled_file_events() {
+        let path = std::env::temp_dir().join(format!("pulso-events-{}", std::process::id()));
+        let target = EventTarget::File(path.clone());
+        let mut events = EventLog::open(&target, "eth0", 2).unwrap().addresses(false);
+        for (secs, port) in [(1697723025, 22), (1697723026, 80), (1697723027, 443)] {
+            events.record(Direction::In, &headers(secs, port)).unwrap();
+        }
+        drop(events);
+
+        let source = IpAddress::V4([192, 0, 2, 1]);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "1697723025.002500 connection inbound src={source} dport=22 device=eth0\n\
+                 1697723027.002500 connection inbound src={source} dport=443 device=eth0\n"
+            )
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_events_with_addresses() {
+        let path = std::env::temp_dir().join(format!("pulso-events-ip-{}", std::process::id()));
+        let target = EventTarget::File(path.clone());
+        let events = EventLog::open(&target, "eth0", 1).unwrap().addresses(true);
+        assert_eq!(
+            events.format(Direction::In, &headers(1697723025, 22)),
+            "1697723025.002500 connection inbound src=192.0.2.1 dport=22 device=eth0"
+        );
+        drop(events);
+        fs::remove_file(path).unwrap();
+    }
+}
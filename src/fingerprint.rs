@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::aggregate::Table;
+use crate::capture::{ExtractedHeaders, SynHeaders};
+use crate::collector::Mergeable;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+const OPTION_WINDOW_SCALE: u8 = 3;
+
+/// TCP SYN signature in the style of JA4T: `window_options_mss_wscale`,
+/// for example `64240_2-4-8-1-3_1460_7`. absent values are written as `00`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    pub window_size: u16,
+    /// option kinds in the order they were sent
+    pub option_kinds: Vec<u8>,
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+}
+
+impl Fingerprint {
+    pub fn from_syn(syn: &SynHeaders) -> Self {
+        let mut fingerprint = Fingerprint {
+            window_size: syn.window_size,
+            option_kinds: Vec::new(),
+            mss: None,
+            window_scale: None,
+        };
+
+        let mut options = &syn.options[..];
+        while let Some((&kind, rest)) = options.split_first() {
+            fingerprint.option_kinds.push(kind);
+            match kind {
+                OPTION_END => break,
+                OPTION_NOOP => {
+                    options = rest;
+                    continue;
+                }
+                _ => (),
+            }
+            // length includes the kind and length bytes
+            let len = match rest.first() {
+                Some(&len) if len >= 2 && len as usize <= options.len() => len as usize,
+                _ => break, // malformed or truncated
+            };
+            let value = &options[2..len];
+            match (kind, value) {
+                (OPTION_MSS, &[hi, lo]) => fingerprint.mss = Some(u16::from_be_bytes([hi, lo])),
+                (OPTION_WINDOW_SCALE, &[scale]) => fingerprint.window_scale = Some(scale),
+                _ => (),
+            }
+            options = &options[len..];
+        }
+
+        fingerprint
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_", self.window_size)?;
+        if self.option_kinds.is_empty() {
+            write!(f, "00")?;
+        }
+        for (i, kind) in self.option_kinds.iter().enumerate() {
+            if i > 0 {
+                write!(f, "-")?;
+            }
+            write!(f, "{kind}")?;
+        }
+        match self.mss {
+            Some(mss) => write!(f, "_{mss}")?,
+            None => write!(f, "_00")?,
+        }
+        match self.window_scale {
+            Some(scale) => write!(f, "_{scale}"),
+            None => write!(f, "_00"),
+        }
+    }
+}
+
+/// counts distinct SYN fingerprints per destination port
+#[derive(Debug, Default)]
+pub struct FingerprintCounter {
+    pub(crate) counts: HashMap<(u16, Fingerprint), u64>,
+}
+
+impl Mergeable for FingerprintCounter {
+    fn merge(&mut self, other: Self) {
+        self.counts.merge(other.counts);
+    }
+}
+
+impl FingerprintCounter {
+    pub fn process(&mut self, headers: &ExtractedHeaders) {
+        let fingerprint = Fingerprint::from_syn(&headers.syn);
+        *self
+            .counts
+            .entry((headers.dest_port, fingerprint))
+            .or_default() += 1;
+    }
+
+    /// one row per port, with a count for each fingerprint seen on that port
+    pub fn table(&self) -> Table {
+        let mut grouped: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        for ((port, fingerprint), count) in &self.counts {
+            *grouped
+                .entry(port.to_string())
+                .or_default()
+                .entry(fingerprint.to_string())
+                .or_default() += count;
+        }
+        Table::from_grouped(grouped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::SynHeaders;
+    use crate::fingerprint::Fingerprint;
+
+    fn syn(window_size: u16, options: &[u8]) -> SynHeaders {
+        SynHeaders {
+            window_size,
+            options: options.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_linux_syn() {
+        // mss 65495, sack permitted, timestamps, nop, window scale 7
+        let options = [
+            2, 4, 0xff, 0xd7, 4, 2, 8, 10, 0, 0, 0, 1, 0, 0, 0, 0, 1, 3, 3, 7,
+        ];
+        let fingerprint = Fingerprint::from_syn(&syn(65495, &options));
+        assert_eq!(fingerprint.to_string(), "65495_2-4-8-1-3_65495_7");
+    }
+
+    #[test]
+    fn test_no_options() {
+        let fingerprint = Fingerprint::from_syn(&syn(1024, &[]));
+        assert_eq!(fingerprint.to_string(), "1024_00_00_00");
+    }
+
+    #[test]
+    fn test_truncated_options() {
+        let fingerprint = Fingerprint::from_syn(&syn(512, &[1, 2, 4, 5]));
+        assert_eq!(fingerprint.to_string(), "512_1-2_00_00");
+    }
+}
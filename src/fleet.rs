@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::aggregate::{Digest, Row, Section, Table};
+use crate::collector::unix_now;
+use crate::json::{array, each, get, nanos, number, port, text, tuple};
+use crate::output::{hostname, Sink};
+
+/// environment variable holding the key shared by agents and their server
+pub const KEY_VARIABLE: &str = "PULSO_FLEET_KEY";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// agents connect for each window, so idle connections are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// reports sent further than this from the server's clock are rejected, as possible replays
+const MAX_SKEW: Duration = Duration::from_secs(300);
+/// longest report line accepted. reports carry the tables of a window but not its flows
+const MAX_REPORT: u64 = 4 << 20;
+/// agents served at once. further connections are refused until one of them closes
+const MAX_AGENTS: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// the fleet key from the environment
+pub fn key_from_env() -> Result<Vec<u8>> {
+    match std::env::var(KEY_VARIABLE) {
+        Ok(key) if !key.is_empty() => Ok(key.into_bytes()),
+        _ => Err(anyhow!(
+            "environment variable {KEY_VARIABLE} must be non-empty"
+        )),
+    }
+}
+
+fn mac(key: &[u8], body: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(body.as_bytes());
+    mac
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// a report line: the hex HMAC-SHA256 of the JSON, a space and the JSON
+pub fn sign(key: &[u8], report: &Value) -> String {
+    let body = report.to_string();
+    let signature = mac(key, &body).finalize().into_bytes();
+    format!("{} {body}", hex(&signature))
+}
+
+/// the JSON of a report line, if it was signed with the key
+pub fn verify(key: &[u8], line: &str) -> Result<Value> {
+    let (signature, body) = line
+        .split_once(' ')
+        .ok_or(anyhow!("expected <signature> <report>"))?;
+    let signature = unhex(signature).ok_or(anyhow!("invalid signature"))?;
+    mac(key, body)
+        .verify_slice(&signature)
+        .map_err(|_| anyhow!("bad signature"))?;
+    serde_json::from_str(body).context("invalid report")
+}
+
+/// a window reported by an agent. per-key connection records stay on the agent, so addresses only
+/// appear as the labels of the sections, which are pseudonyms with the "privacy" feature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub host: String,
+    pub device: String,
+    /// unix time at which the report was sent
+    pub sent: Duration,
+    pub digest: Digest,
+}
+
+impl Report {
+    pub fn to_json(&self) -> Value {
+        let digest = &self.digest;
+        let ports: Vec<Value> = digest
+            .ports
+            .iter()
+            .map(|(direction, port, count)| json!([direction.short(), port, count]))
+            .collect();
+        let sections: Vec<Value> = digest
+            .sections
+            .iter()
+            .map(|section| {
+                let rows: Vec<Value> = section
+                    .table
+                    .rows
+                    .iter()
+                    .map(|row| json!([row.group, row.total, row.items]))
+                    .collect();
+                json!({ "title": section.title, "columns": section.columns, "rows": rows })
+            })
+            .collect();
+        json!({
+            "host": self.host,
+            "device": self.device,
+            "sent": nanos(self.sent),
+            "start": nanos(digest.start),
+            "end": nanos(digest.end),
+            "periodic": digest.periodic,
+            "connections": digest.connections,
+            "sources": digest.sources,
+            "dropped": digest.dropped,
+            "ports": ports,
+            "sections": sections,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let section = |section: &Value| -> Result<Section> {
+            let title = match get(section, "title")? {
+                Value::Null => None,
+                title => Some(text(title)?.to_string()),
+            };
+            let columns = array(get(section, "columns")?)?
+                .iter()
+                .map(|column| Ok(text(column)?.to_string()))
+                .collect::<Result<_>>()?;
+            let rows = each(section, "rows", |row| {
+                let [group, total, items] = tuple(row)?;
+                let items = array(items)?
+                    .iter()
+                    .map(|item| {
+                        let [label, count] = tuple(item)?;
+                        Ok((text(label)?.to_string(), number(count)?))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(Row {
+                    group: text(group)?.to_string(),
+                    total: number(total)?,
+                    items,
+                })
+            })?;
+            Ok(Section {
+                title,
+                columns,
+                table: Table { rows },
+            })
+        };
+        let digest = Digest {
+            start: Duration::from_nanos(number(get(value, "start")?)?),
+            end: Duration::from_nanos(number(get(value, "end")?)?),
+            periodic: get(value, "periodic")?
+                .as_bool()
+                .ok_or(anyhow!("expected periodic to be a boolean"))?,
+            connections: number(get(value, "connections")?)?,
+            ports: each(value, "ports", |count| {
+                let [direction, port_value, count] = tuple(count)?;
+                Ok((text(direction)?.parse()?, port(port_value)?, number(count)?))
+            })?,
+            sources: number(get(value, "sources")?)?,
+            dropped: number(get(value, "dropped")?)?,
+            flows: Vec::new(),
+            sections: each(value, "sections", section)?,
+        };
+        Ok(Report {
+            host: text(get(value, "host")?)?.to_string(),
+            device: text(get(value, "device")?)?.to_string(),
+            sent: Duration::from_nanos(number(get(value, "sent")?)?),
+            digest,
+        })
+    }
+}
+
+/// windows reported by agents, merged into one
+#[derive(Debug, Default)]
+pub struct Fleet {
+    digest: Digest,
+    /// when each window was reported by its host and device, so that it is only counted once
+    reported: HashMap<(String, String, Duration), Duration>,
+}
+
+impl Fleet {
+    /// adds a report to the merged window, unless it was sent too long ago or was already received
+    pub fn receive(&mut self, report: Report, now: Duration) -> Result<()> {
+        if report.sent + MAX_SKEW < now || report.sent > now + MAX_SKEW {
+            bail!(
+                "report sent at {} is too far from the server's clock",
+                report.sent.as_secs()
+            );
+        }
+        // anything older would be rejected above
+        self.reported.retain(|_, sent| *sent + MAX_SKEW * 2 >= now);
+        let window = (report.host, report.device, report.digest.start);
+        if self.reported.contains_key(&window) {
+            bail!(
+                "window {} of {} {} was already reported",
+                window.2.as_secs(),
+                window.0,
+                window.1
+            );
+        }
+        info!(
+            "{} {} reported {} connections",
+            window.0, window.1, report.digest.connections
+        );
+        self.reported.insert(window, report.sent);
+        self.digest.merge(&report.digest);
+        Ok(())
+    }
+
+    /// the merged windows received since the last call, or None if there were none
+    pub fn take(&mut self) -> Option<Digest> {
+        let digest = std::mem::take(&mut self.digest);
+        (digest.end > Duration::ZERO).then_some(digest)
+    }
+}
+
+/// one of the connections counted towards [MAX_AGENTS], given back when dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// accepts agents on a listener, adding their reports to the fleet. each line received is
+/// answered with `ok` or `error: <reason>`
+pub fn serve(listener: TcpListener, key: Vec<u8>, fleet: Arc<Mutex<Fleet>>) {
+    let key = Arc::new(key);
+    let connected = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("accept error: {e}");
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_addr()
+            .map_or("-".to_string(), |addr| addr.to_string());
+        if connected.fetch_add(1, Ordering::SeqCst) >= MAX_AGENTS {
+            connected.fetch_sub(1, Ordering::SeqCst);
+            warn!("agent {peer}: refused, {MAX_AGENTS} agents already connected");
+            let _ = stream.set_write_timeout(Some(TIMEOUT));
+            let _ = writeln!(stream, "error: too many connections");
+            continue;
+        }
+        let slot = Slot(connected.clone());
+        let (key, fleet) = (key.clone(), fleet.clone());
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(e) = receive(stream, &key, &fleet) {
+                warn!("agent {peer}: {e:#}");
+            }
+        });
+    }
+}
+
+fn receive(stream: TcpStream, key: &[u8], fleet: &Mutex<Fleet>) -> Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if (&mut reader).take(MAX_REPORT).read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if !line.ends_with('\n') {
+            writeln!(writer, "error: report too long")?;
+            bail!("report too long");
+        }
+        let received = verify(key, line.trim_end())
+            .and_then(|value| Report::from_json(&value))
+            .and_then(|report| fleet.lock().unwrap().receive(report, unix_now()));
+        match received {
+            Ok(()) => writeln!(writer, "ok")?,
+            Err(e) => {
+                warn!("rejected report: {e:#}");
+                writeln!(writer, "error: {e:#}")?;
+            }
+        }
+    }
+}
+
+/// windows reported to a `pulso server` over TCP, signed with the fleet key.
+/// a connection is made for each window
+pub struct Agent {
+    addr: String,
+    key: Vec<u8>,
+    host: String,
+    device: String,
+}
+
+impl Agent {
+    pub fn new(addr: &str, key: Vec<u8>, device: &str) -> Self {
+        Agent {
+            addr: addr.to_string(),
+            key,
+            host: hostname(),
+            device: device.to_string(),
+        }
+    }
+
+    /// name reported instead of the hostname
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+}
+
+impl Sink for Agent {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let report = Report {
+            host: self.host.clone(),
+            device: self.device.clone(),
+            sent: unix_now(),
+            digest: Digest {
+                flows: Vec::new(),
+                ..digest.clone()
+            },
+        };
+        let addr = self
+            .addr
+            .to_socket_addrs()
+            .with_context(|| format!("resolve server {}", self.addr))?
+            .next()
+            .ok_or(anyhow!("no address for server {}", self.addr))?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)
+            .with_context(|| format!("connect to server {addr}"))?;
+        let line = sign(&self.key, &report.to_json());
+        if line.len() as u64 >= MAX_REPORT {
+            bail!("report of {} bytes is too long for the server", line.len());
+        }
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        writeln!(stream, "{line}").context("send report")?;
+
+        let mut response = String::new();
+        BufReader::new(stream)
+            .read_line(&mut response)
+            .with_context(|| format!("read response from server {addr}"))?;
+        match response.trim_end() {
+            "ok" => Ok(()),
+            response => Err(anyhow!("server {addr} rejected the report: {response}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::aggregate::{Digest, Row, Section, Table};
+    use crate::capture::Direction;
+    use crate::collector::unix_now;
+    use crate::fleet::{serve, sign, verify, Agent, Fleet, Report, MAX_AGENTS};
+    use crate::output::Sink;
+
+    const KEY: &[u8] = b"fleet key";
+
+    fn digest(start: u64, source: &str, count: u64) -> Digest {
+        let row = Row {
+            group: source.to_string(),
+            total: count,
+            items: vec![("22".to_string(), count)],
+        };
+        Digest {
+            start: Duration::from_secs(start),
+            end: Duration::from_secs(start + 60),
+            periodic: true,
+            connections: count,
+            ports: vec![(Direction::In, 22, count)],
+            sources: 1,
+            sections: vec![Section::new(
+                None,
+                &["src", "dport"],
+                Table { rows: vec![row] },
+            )],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_signed_report() {
+        let report = Report {
+            host: "web1".into(),
+            device: "eth0".into(),
+            sent: Duration::from_millis(1697723060123),
+            digest: digest(1697723000, "4a4e1d2b5c6f7a8e", 3),
+        };
+        let line = sign(KEY, &report.to_json());
+        let value = verify(KEY, &line).unwrap();
+        assert_eq!(Report::from_json(&value).unwrap(), report);
+
+        assert_eq!(
+            verify(b"other key", &line).unwrap_err().to_string(),
+            "bad signature"
+        );
+        let tampered = line.replace("\"connections\":3", "\"connections\":4");
+        assert_eq!(
+            verify(KEY, &tampered).unwrap_err().to_string(),
+            "bad signature"
+        );
+        assert!(verify(KEY, "zz {}").is_err());
+    }
+
+    #[test]
+    fn test_agents_to_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let fleet = Arc::new(Mutex::new(Fleet::default()));
+        let server_fleet = fleet.clone();
+        thread::spawn(move || serve(listener, KEY.to_vec(), server_fleet));
+
+        let start = unix_now().as_secs() - 60;
+        let mut web1 = Agent::new(&addr, KEY.to_vec(), "eth0").host("web1");
+        let mut web2 = Agent::new(&addr, KEY.to_vec(), "eth0").host("web2");
+        web1.digest(&digest(start, "aa", 3)).unwrap();
+        web2.digest(&digest(start, "aa", 2)).unwrap();
+        web2.digest(&digest(start + 60, "bb", 4)).unwrap();
+
+        let replayed = web1.digest(&digest(start, "aa", 3)).unwrap_err();
+        assert!(replayed.to_string().ends_with("was already reported"));
+        let mut impostor = Agent::new(&addr, b"wrong".to_vec(), "eth0").host("web3");
+        let rejected = impostor.digest(&digest(start, "cc", 1)).unwrap_err();
+        assert!(rejected
+            .to_string()
+            .ends_with("rejected the report: error: bad signature"));
+
+        let merged = fleet.lock().unwrap().take().unwrap();
+        assert_eq!(merged.start, Duration::from_secs(start));
+        assert_eq!(merged.end, Duration::from_secs(start + 120));
+        assert_eq!(merged.connections, 9);
+        assert_eq!(merged.ports, [(Direction::In, 22, 9)]);
+        let mut out = Vec::new();
+        merged.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("# window {start} {}\naa:5 22:5\nbb:4 22:4\n", start + 120)
+        );
+        assert_eq!(fleet.lock().unwrap().take(), None);
+    }
+
+    #[test]
+    fn test_too_many_agents() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fleet = Arc::new(Mutex::new(Fleet::default()));
+        thread::spawn(move || serve(listener, KEY.to_vec(), fleet));
+
+        let idle: Vec<TcpStream> = (0..MAX_AGENTS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        let mut response = String::new();
+        BufReader::new(TcpStream::connect(addr).unwrap())
+            .read_line(&mut response)
+            .unwrap();
+        assert_eq!(response, "error: too many connections\n");
+
+        drop(idle);
+        let start = unix_now().as_secs() - 60;
+        let mut agent = Agent::new(&addr.to_string(), KEY.to_vec(), "eth0").host("web1");
+        // the server notices the closed connections once it reads their end
+        let sent = (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            agent.digest(&digest(start, "aa", 1)).is_ok()
+        });
+        assert!(sent);
+    }
+
+    #[test]
+    fn test_stale_report() {
+        let now = Duration::from_secs(1697723060);
+        let report = Report {
+            host: "web1".into(),
+            device: "eth0".into(),
+            sent: now - Duration::from_secs(600),
+            digest: digest(1697722400, "aa", 1),
+        };
+        let error = Fleet::default().receive(report, now).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "report sent at 1697722460 is too far from the server's clock"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::alert::{Alert, AlertKind, Detector};
+use crate::capture::{Direction, ExtractedHeaders};
+
+/// weight of the most recent interval in the baseline
+const ALPHA: f64 = 0.1;
+/// intervals observed on a port before its baseline is trusted
+const WARMUP: u64 = 6;
+
+/// inbound SYNs and the SYN-ACKs sent in reply, counted per interval for one port
+#[derive(Debug, Default)]
+struct PortRate {
+    interval: u64,
+    syns: u64,
+    answers: u64,
+    /// exponentially weighted moving average of SYNs per interval
+    baseline: Option<f64>,
+    intervals_seen: u64,
+    flooding: bool,
+}
+
+/// compares the SYN rate of each port with its own baseline, and the share of SYNs left
+/// unanswered with a fixed limit. intervals are measured using capture timestamps.
+pub struct SynFloodDetector {
+    interval: Duration,
+    /// alert when the SYNs in an interval exceed the baseline by this factor
+    factor: Option<f64>,
+    /// alert when more than this fraction of the SYNs in an interval were unanswered
+    max_unanswered: Option<f64>,
+    /// SYNs per interval below which no alert is raised
+    min_syns: u64,
+    ports: HashMap<u16, PortRate>,
+}
+
+impl SynFloodDetector {
+    pub fn new(
+        interval: Duration,
+        factor: Option<f64>,
+        max_unanswered: Option<f64>,
+        min_syns: u64,
+    ) -> Self {
+        SynFloodDetector {
+            interval,
+            factor,
+            max_unanswered,
+            min_syns,
+            ports: HashMap::new(),
+        }
+    }
+
+    fn interval_index(&self, ts: Duration) -> u64 {
+        (ts.as_nanos() / self.interval.as_nanos().max(1)) as u64
+    }
+
+    /// closes the current interval of a port if the timestamp falls in a later one
+    fn roll(&mut self, port: u16, ts: Duration, alerts: &mut Vec<Alert>) {
+        let index = self.interval_index(ts);
+        let (interval, max_unanswered, min_syns) =
+            (self.interval, self.max_unanswered, self.min_syns);
+        let rate = self.ports.entry(port).or_insert_with(|| PortRate {
+            interval: index,
+            ..Default::default()
+        });
+        if index <= rate.interval {
+            return;
+        }
+
+        if let Some(max_unanswered) = max_unanswered {
+            let unanswered = rate.syns.saturating_sub(rate.answers);
+            if rate.syns >= min_syns
+                && rate.syns > 0
+                && unanswered as f64 > max_unanswered * rate.syns as f64
+            {
+                alerts.push(Alert {
+                    ts: Duration::from_nanos((rate.interval + 1) * interval.as_nanos() as u64),
+                    kind: AlertKind::UnansweredSyns,
+                    direction: Direction::In,
+                    source: None,
+                    port: Some(port),
+                    value: unanswered * 100 / rate.syns,
+                    threshold: (max_unanswered * 100.0) as u64,
+                    window: interval,
+                    rule: None,
+                    rule_index: None,
+                });
+            }
+        }
+
+        // a flood is not allowed to become the new normal
+        if !rate.flooding {
+            let skipped = (index - rate.interval - 1) as i32;
+            rate.baseline = Some(match rate.baseline {
+                Some(baseline) => {
+                    let baseline = ALPHA * rate.syns as f64 + (1.0 - ALPHA) * baseline;
+                    baseline * (1.0 - ALPHA).powi(skipped)
+                }
+                None => rate.syns as f64 * (1.0 - ALPHA).powi(skipped),
+            });
+        }
+        rate.intervals_seen += index - rate.interval;
+        rate.interval = index;
+        rate.syns = 0;
+        rate.answers = 0;
+        rate.flooding = false;
+    }
+}
+
+impl Detector for SynFloodDetector {
+    fn observe(
+        &mut self,
+        direction: Direction,
+        headers: &ExtractedHeaders,
+        alerts: &mut Vec<Alert>,
+    ) {
+        if direction != Direction::In {
+            return;
+        }
+        let ts = headers.timestamp();
+        self.roll(headers.dest_port, ts, alerts);
+
+        let (interval, factor, min_syns) = (self.interval, self.factor, self.min_syns);
+        let rate = self.ports.get_mut(&headers.dest_port).expect("rolled");
+        rate.syns += 1;
+
+        if let (Some(factor), Some(baseline)) = (factor, rate.baseline) {
+            let threshold = (baseline * factor).ceil().max(min_syns as f64) as u64;
+            if rate.intervals_seen >= WARMUP && !rate.flooding && rate.syns > threshold {
+                rate.flooding = true;
+                alerts.push(Alert {
+                    ts,
+                    kind: AlertKind::SynFlood,
+                    direction,
+                    source: None,
+                    port: Some(headers.dest_port),
+                    value: rate.syns,
+                    threshold,
+                    window: interval,
+                    rule: None,
+                    rule_index: None,
+                });
+            }
+        }
+    }
+
+    fn observe_answer(&mut self, headers: &ExtractedHeaders, alerts: &mut Vec<Alert>) {
+        // the reply is sent from the port that was connected to
+        self.roll(headers.source_port, headers.timestamp(), alerts);
+        if let Some(rate) = self.ports.get_mut(&headers.source_port) {
+            rate.answers += 1;
+        }
+    }
+
+    fn wants_answers(&self) -> bool {
+        self.max_unanswered.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use libc::timeval;
+
+    use crate::alert::{AlertKind, Detector};
+    use crate::capture::{Direction, ExtractedHeaders};
+    use crate::flood::SynFloodDetector;
+    use crate::sensitive::IpAddress;
+
+    fn headers(millis: i64, source_port: u16, dest_port: u16) -> ExtractedHeaders {
+        ExtractedHeaders {
+            source_ip: IpAddress::V4([192, 0, 2, 1]),
+            source_port,
+            dest_ip: IpAddress::V4([10, 0, 0, 1]),
+            dest_port,
+            capture_ts: timeval {
+                tv_sec: millis / 1000,
+                tv_usec: (millis % 1000) * 1000,
+            },
+            syn: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_flood_above_baseline() {
+        let mut detector = SynFloodDetector::new(Duration::from_secs(1), Some(3.0), None, 5);
+        let mut alerts = Vec::new();
+
+        // steady 5 per second
+        for second in 0..10 {
+            for i in 0..5 {
+                detector.observe(
+                    Direction::In,
+                    &headers(second * 1000 + i * 100, 40000, 80),
+                    &mut alerts,
+                );
+            }
+        }
+        assert!(alerts.is_empty());
+
+        // 50 in the next second
+        for i in 0..50 {
+            detector.observe(
+                Direction::In,
+                &headers(10_000 + i * 10, 40000, 80),
+                &mut alerts,
+            );
+        }
+        assert_eq!(alerts.len(), 1, "alert once per interval");
+        assert_eq!(alerts[0].kind, AlertKind::SynFlood);
+        assert_eq!(alerts[0].port, Some(80));
+        assert_eq!(alerts[0].threshold, 15);
+        assert_eq!(alerts[0].value, 16);
+    }
+
+    #[test]
+    fn test_no_alert_during_warmup() {
+        let mut detector = SynFloodDetector::new(Duration::from_secs(1), Some(2.0), None, 1);
+        let mut alerts = Vec::new();
+
+        for i in 0..100 {
+            detector.observe(Direction::In, &headers(1000 + i, 40000, 80), &mut alerts);
+        }
+        assert!(alerts.is_empty());
+    }
+
+    #[test]
+    fn test_unanswered_syns() {
+        let mut detector = SynFloodDetector::new(Duration::from_secs(1), None, Some(0.5), 4);
+        let mut alerts = Vec::new();
+
+        for i in 0..10 {
+            detector.observe(
+                Direction::In,
+                &headers(i * 10, 40000 + i as u16, 22),
+                &mut alerts,
+            );
+        }
+        for i in 0..3 {
+            detector.observe_answer(&headers(i * 10 + 1, 22, 40000 + i as u16), &mut alerts);
+        }
+        assert!(alerts.is_empty());
+
+        // next interval closes the first one
+        detector.observe(Direction::In, &headers(1500, 40100, 22), &mut alerts);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::UnansweredSyns);
+        assert_eq!(alerts[0].value, 70);
+        assert_eq!(alerts[0].ts, Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::aggregate::Digest;
+use crate::output::Sink;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// the measurements of a window as plaintext lines, timestamped with its end in seconds
+pub fn lines(digest: &Digest) -> String {
+    let ts = digest.end.as_secs();
+    let mut out = format!(
+        "pulso.connections.total {} {ts}\n\
+         pulso.sources {} {ts}\n\
+         pulso.pcap.dropped {} {ts}\n",
+        digest.connections, digest.sources, digest.dropped
+    );
+    for (direction, port, count) in &digest.ports {
+        out.push_str(&format!(
+            "pulso.connections.{direction}.{port} {count} {ts}\n"
+        ));
+    }
+    out
+}
+
+/// Graphite plaintext protocol over TCP. a connection is made for each window
+pub struct Graphite {
+    addr: String,
+}
+
+impl Graphite {
+    pub fn new(addr: &str) -> Self {
+        Graphite {
+            addr: addr.to_string(),
+        }
+    }
+}
+
+impl Sink for Graphite {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let addr = self
+            .addr
+            .to_socket_addrs()
+            .with_context(|| format!("resolve graphite {}", self.addr))?
+            .next()
+            .ok_or(anyhow!("no address for graphite {}", self.addr))?;
+        let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+            .with_context(|| format!("connect to graphite {addr}"))?;
+        stream
+            .write_all(lines(digest).as_bytes())
+            .context("send to graphite")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::aggregate::Digest;
+    use crate::capture::Direction;
+    use crate::graphite::Graphite;
+    use crate::output::Sink;
+
+    #[test]
+    fn test_graphite_digest() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let digest = Digest {
+            end: Duration::from_secs(1697723025),
+            connections: 3,
+            ports: vec![(Direction::In, 22, 2), (Direction::Out, 443, 1)],
+            sources: 1,
+            ..Default::default()
+        };
+        Graphite::new(&addr).digest(&digest).unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            "pulso.connections.total 3 1697723025\n\
+             pulso.sources 1 1697723025\n\
+             pulso.pcap.dropped 0 1697723025\n\
+             pulso.connections.inbound.22 2 1697723025\n\
+             pulso.connections.outbound.443 1 1697723025\n"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::alert::{Alert, AlertKind};
+use crate::capture::Direction;
+use crate::http::{self, Url};
+use crate::sensitive::IpAddress;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// what to do when an alert is raised. the alert is passed along as a JSON object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// run a command with the event on stdin
+    Exec(Vec<String>),
+    /// POST the event to a URL
+    Webhook(Url),
+}
+
+impl Action {
+    pub fn run(&self, event: &[u8]) -> Result<()> {
+        match self {
+            Action::Exec(command) => {
+                let (program, args) = command.split_first().ok_or(anyhow!("empty command"))?;
+                let mut child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .spawn()
+                    .with_context(|| format!("run {program}"))?;
+                // stdin is closed before waiting, and the child is always waited for
+                let written = child.stdin.take().expect("piped stdin").write_all(event);
+                let status = child.wait()?;
+                written.with_context(|| format!("write event to {program}"))?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(anyhow!("{program} exited with {status}"))
+                }
+            }
+            Action::Webhook(url) => http::post(url, "application/json", event, WEBHOOK_TIMEOUT),
+        }
+    }
+}
+
+type AlertIdentity = (
+    AlertKind,
+    Direction,
+    Option<IpAddress>,
+    Option<u16>,
+    Option<usize>,
+);
+
+/// suppresses repeats of the same alert within a quiet period,
+/// and limits the number of alerts let through per minute
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    quiet: Duration,
+    max_per_minute: usize,
+    last_allowed: HashMap<AlertIdentity, Duration>,
+    recent: VecDeque<Duration>,
+}
+
+impl Throttle {
+    pub fn new(quiet: Duration, max_per_minute: usize) -> Self {
+        Throttle {
+            quiet,
+            max_per_minute,
+            last_allowed: HashMap::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn allow(&mut self, alert: &Alert) -> bool {
+        let now = alert.ts;
+        let identity = (
+            alert.kind,
+            alert.direction,
+            alert.source,
+            alert.port,
+            alert.rule_index,
+        );
+        if let Some(&last) = self.last_allowed.get(&identity) {
+            if now.saturating_sub(last) < self.quiet {
+                return false;
+            }
+        }
+
+        while let Some(&ts) = self.recent.front() {
+            if now.saturating_sub(ts) < Duration::from_secs(60) {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.max_per_minute {
+            return false;
+        }
+
+        let quiet = self.quiet;
+        self.last_allowed
+            .retain(|_, &mut last| now.saturating_sub(last) < quiet);
+        self.last_allowed.insert(identity, now);
+        self.recent.push_back(now);
+        true
+    }
+}
+
+/// an action taken for alerts raised by one rule, or for every alert
+#[derive(Debug, Clone)]
+pub struct Hook {
+    /// position of the rule among the loaded rules
+    rule: Option<usize>,
+    action: Action,
+    throttle: Throttle,
+}
+
+impl Hook {
+    pub fn new(rule: Option<usize>, action: Action, throttle: Throttle) -> Self {
+        Hook {
+            rule,
+            action,
+            throttle,
+        }
+    }
+
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+
+    /// whether the action should be taken for this alert
+    pub fn accept(&mut self, alert: &Alert) -> bool {
+        let matches = match self.rule {
+            Some(rule) => alert.rule_index == Some(rule),
+            None => true,
+        };
+        matches && self.throttle.allow(alert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use crate::alert::{Alert, AlertKind};
+    use crate::capture::Direction;
+    use crate::hooks::{Action, Hook, Throttle};
+    use crate::sensitive::IpAddress;
+
+    fn alert(secs: u64, source: u8) -> Alert {
+        Alert {
+            ts: Duration::from_secs(secs),
+            kind: AlertKind::VerticalScan,
+            direction: Direction::In,
+            source: Some(IpAddress::V4([192, 0, 2, source])),
+            port: None,
+            value: 11,
+            threshold: 10,
+            window: Duration::from_secs(60),
+            rule: None,
+            rule_index: None,
+        }
+    }
+
+    #[test]
+    fn test_throttle_deduplicates() {
+        let mut throttle = Throttle::new(Duration::from_secs(300), 10);
+        assert!(throttle.allow(&alert(0, 1)));
+        assert!(!throttle.allow(&alert(100, 1)));
+        assert!(throttle.allow(&alert(100, 2)));
+        assert!(throttle.allow(&alert(300, 1)));
+    }
+
+    #[test]
+    fn test_throttle_rate_limit() {
+        let mut throttle = Throttle::new(Duration::ZERO, 2);
+        assert!(throttle.allow(&alert(0, 1)));
+        assert!(throttle.allow(&alert(1, 2)));
+        assert!(!throttle.allow(&alert(2, 3)));
+        assert!(throttle.allow(&alert(60, 3)));
+    }
+
+    #[test]
+    fn test_hook_matches_rule() {
+        let throttle = Throttle::new(Duration::ZERO, 10);
+        let action = Action::Exec(vec!["true".into()]);
+        let mut hook = Hook::new(Some(1), action, throttle);
+        assert!(!hook.accept(&alert(0, 1)));
+
+        let mut ruled = alert(0, 1);
+        ruled.rule = Some("sources>1 per 1s".into());
+        ruled.rule_index = Some(0);
+        assert!(!hook.accept(&ruled), "same condition in another rule");
+
+        ruled.rule_index = Some(1);
+        assert!(hook.accept(&ruled));
+    }
+
+    #[test]
+    fn test_exec_receives_event() {
+        let path = std::env::temp_dir().join(format!("pulso-exec-{}", std::process::id()));
+        let action = Action::Exec(vec!["tee".into(), path.display().to_string()]);
+        action.run(b"{\"kind\":\"test\"}").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"kind\":\"test\"}");
+        fs::remove_file(path).unwrap();
+
+        assert!(Action::Exec(vec!["false".into()]).run(b"").is_err());
+    }
+}
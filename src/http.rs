@@ -0,0 +1,152 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error, Result};
+
+/// location of a plain HTTP endpoint, such as a local agent or webhook receiver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl FromStr for Url {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or(anyhow!("unsupported url: {s} (only http:// is supported)"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .with_context(|| format!("invalid port in url: {s}"))?,
+            ),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(anyhow!("missing host in url: {s}"));
+        }
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// sends a request body and fails unless the response status is 2xx
+pub fn post(url: &Url, content_type: &str, body: &[u8], timeout: Duration) -> Result<()> {
+    let addr = (url.host.trim_matches(['[', ']']), url.port)
+        .to_socket_addrs()
+        .with_context(|| format!("resolve {url}"))?
+        .next()
+        .ok_or(anyhow!("no address for {url}"))?;
+    let mut stream =
+        TcpStream::connect_timeout(&addr, timeout).with_context(|| format!("connect to {url}"))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\n\
+         Host: {}:{}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        url.path,
+        url.host,
+        url.port,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .with_context(|| format!("read response from {url}"))?;
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(anyhow!("{url} responded with {:?}", status_line.trim_end())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::http::{post, Url};
+
+    #[test]
+    fn test_parse_url() {
+        let url: Url = "http://localhost:8086/write?db=pulso".parse().unwrap();
+        assert_eq!(url.host, "localhost");
+        assert_eq!(url.port, 8086);
+        assert_eq!(url.path, "/write?db=pulso");
+
+        let url: Url = "http://[::1]".parse().unwrap();
+        assert_eq!(
+            (url.host.as_str(), url.port, url.path.as_str()),
+            ("[::1]", 80, "/")
+        );
+
+        assert!("https://example.com".parse::<Url>().is_err());
+    }
+
+    #[test]
+    fn test_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(len) = line.strip_prefix("Content-Length: ") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            (request, String::from_utf8(body).unwrap())
+        });
+
+        let url = format!("http://127.0.0.1:{port}/hook").parse().unwrap();
+        post(&url, "application/json", b"{}", Duration::from_secs(1)).unwrap();
+
+        let (request, body) = server.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/json\r\n"));
+        assert_eq!(body, "{}");
+    }
+}
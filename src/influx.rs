@@ -0,0 +1,127 @@
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::aggregate::Digest;
+use crate::http::{self, Url};
+use crate::output::Sink;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// escapes commas, spaces and equal signs in tag keys and values
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(' ', "\\ ")
+        .replace('=', "\\=")
+}
+
+/// the measurements of a window, timestamped with its end in nanoseconds:
+///
+/// - `pulso_window connections=,sources=,dropped=`
+/// - `pulso_connections,direction=,port= count=` per direction and port
+/// - `pulso_digest,section=,<column>=... count=` per digest entry
+pub fn lines(digest: &Digest) -> String {
+    let ts = digest.end.as_nanos();
+    let mut out = format!(
+        "pulso_window connections={}i,sources={}i,dropped={}i {ts}\n",
+        digest.connections, digest.sources, digest.dropped
+    );
+    for (direction, port, count) in &digest.ports {
+        out.push_str(&format!(
+            "pulso_connections,direction={direction},port={port} count={count}i {ts}\n"
+        ));
+    }
+    for section in &digest.sections {
+        for entry in section.entries() {
+            let mut tags = String::new();
+            if let Some(title) = &section.title {
+                tags.push_str(&format!(",section={}", escape(title)));
+            }
+            for (column, value) in &entry.labels {
+                if !value.is_empty() {
+                    tags.push_str(&format!(",{}={}", escape(column), escape(value)));
+                }
+            }
+            out.push_str(&format!("pulso_digest{tags} count={}i {ts}\n", entry.count));
+        }
+    }
+    out
+}
+
+enum Destination {
+    Writer(Box<dyn Write>),
+    /// an InfluxDB write endpoint, such as `/api/v2/write?org=..&bucket=..&precision=ns`
+    Http(Url),
+}
+
+/// InfluxDB line protocol, written out or POSTed to a write endpoint
+pub struct Influx {
+    destination: Destination,
+}
+
+impl Influx {
+    pub fn writer(out: Box<dyn Write>) -> Self {
+        Influx {
+            destination: Destination::Writer(out),
+        }
+    }
+
+    pub fn http(url: Url) -> Self {
+        Influx {
+            destination: Destination::Http(url),
+        }
+    }
+}
+
+impl Sink for Influx {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let lines = lines(digest);
+        match &mut self.destination {
+            Destination::Writer(out) => {
+                out.write_all(lines.as_bytes())?;
+                out.flush()?;
+            }
+            Destination::Http(url) => {
+                http::post(url, "text/plain", lines.as_bytes(), HTTP_TIMEOUT)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::aggregate::{Digest, Row, Section, Table};
+    use crate::capture::Direction;
+    use crate::influx::lines;
+
+    #[test]
+    fn test_lines() {
+        let table = Table {
+            rows: vec![Row {
+                group: "fp 1".into(),
+                total: 2,
+                items: vec![],
+            }],
+        };
+        let digest = Digest {
+            end: Duration::new(1697723025, 500),
+            connections: 7,
+            ports: vec![(Direction::In, 22, 7)],
+            sources: 2,
+            sections: vec![Section::new(Some("in,bound".into()), &["src"], table)],
+            ..Default::default()
+        };
+        assert_eq!(
+            lines(&digest),
+            "pulso_window connections=7i,sources=2i,dropped=0i 1697723025000000500\n\
+             pulso_connections,direction=inbound,port=22 count=7i 1697723025000000500\n\
+             pulso_digest,section=in\\,bound,src=fp\\ 1 count=2i 1697723025000000500\n"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs, UdpSocket};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::aggregate::Digest;
+use crate::capture::Direction;
+use crate::collector::{unix_now, Flow};
+use crate::output::Sink;
+
+const VERSION: u16 = 10;
+const TEMPLATE_SET_ID: u16 = 2;
+const TEMPLATE_V4: u16 = 256;
+const TEMPLATE_V6: u16 = 257;
+/// flows without addresses, whatever their family
+const TEMPLATE_PORTS: u16 = 258;
+const HEADER_LEN: usize = 16;
+/// keeps messages within the usual ethernet MTU
+const MAX_MESSAGE: usize = 1400;
+const TCP: u8 = 6;
+
+/// information element ids and lengths of the fields common to all templates
+const COMMON_FIELDS: [(u16, u16); 6] = [
+    (11, 2),  // destinationTransportPort
+    (4, 1),   // protocolIdentifier
+    (61, 1),  // flowDirection
+    (152, 8), // flowStartMilliseconds
+    (153, 8), // flowEndMilliseconds
+    (3, 8),   // deltaFlowCount
+];
+const V4_FIELDS: [(u16, u16); 2] = [
+    (8, 4),  // sourceIPv4Address
+    (12, 4), // destinationIPv4Address
+];
+const V6_FIELDS: [(u16, u16); 2] = [
+    (27, 16), // sourceIPv6Address
+    (28, 16), // destinationIPv6Address
+];
+
+fn template(id: u16, addresses: &[(u16, u16)]) -> Vec<u8> {
+    let fields: Vec<&(u16, u16)> = addresses.iter().chain(&COMMON_FIELDS).collect();
+    let mut record = Vec::new();
+    record.extend_from_slice(&id.to_be_bytes());
+    record.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+    for (element, length) in fields {
+        record.extend_from_slice(&element.to_be_bytes());
+        record.extend_from_slice(&length.to_be_bytes());
+    }
+    record
+}
+
+/// the template of a flow and its data record. addresses outside of the key are unspecified
+fn record(flow: &Flow, with_addresses: bool) -> (u16, Vec<u8>) {
+    let addresses = [flow.key.source_ip, flow.key.dest_ip].map(|ip| ip.map(|ip| ip.reveal()));
+    let v6 = addresses.iter().flatten().any(IpAddr::is_ipv6);
+
+    let mut record = Vec::new();
+    for address in addresses.into_iter().filter(|_| with_addresses) {
+        match (v6, address) {
+            (false, Some(IpAddr::V4(ip))) => record.extend_from_slice(&ip.octets()),
+            (false, _) => record.extend_from_slice(&Ipv4Addr::UNSPECIFIED.octets()),
+            (true, Some(IpAddr::V6(ip))) => record.extend_from_slice(&ip.octets()),
+            (true, Some(IpAddr::V4(ip))) => record.extend_from_slice(&ip.to_ipv6_mapped().octets()),
+            (true, None) => record.extend_from_slice(&Ipv6Addr::UNSPECIFIED.octets()),
+        }
+    }
+    record.extend_from_slice(&flow.key.dest_port.unwrap_or(0).to_be_bytes());
+    record.push(TCP);
+    record.push(match flow.key.direction {
+        Direction::In => 0,
+        Direction::Out => 1,
+    });
+    record.extend_from_slice(&(flow.first.as_millis() as u64).to_be_bytes());
+    record.extend_from_slice(&(flow.last.as_millis() as u64).to_be_bytes());
+    record.extend_from_slice(&flow.count.to_be_bytes());
+
+    let template = match (with_addresses, v6) {
+        (false, _) => TEMPLATE_PORTS,
+        (true, false) => TEMPLATE_V4,
+        (true, true) => TEMPLATE_V6,
+    };
+    (template, record)
+}
+
+fn set(id: u16, records: &[Vec<u8>]) -> Vec<u8> {
+    let length = 4 + records.iter().map(Vec::len).sum::<usize>();
+    let mut set = Vec::with_capacity(length);
+    set.extend_from_slice(&id.to_be_bytes());
+    set.extend_from_slice(&(length as u16).to_be_bytes());
+    for record in records {
+        set.extend_from_slice(record);
+    }
+    set
+}
+
+/// IPFIX messages over UDP, with a data record per flow of the window.
+/// templates are repeated in every message, as there is no session to carry them.
+///
+/// with the "privacy" feature, records leave out the addresses unless they are requested, as
+/// they would be exported in the clear.
+pub struct Ipfix {
+    socket: UdpSocket,
+    domain: u32,
+    sequence: u32,
+    addresses: bool,
+}
+
+impl Ipfix {
+    pub fn connect(addr: &str, domain: u32) -> Result<Self> {
+        let addr = addr
+            .to_socket_addrs()
+            .with_context(|| format!("resolve ipfix collector {addr}"))?
+            .next()
+            .ok_or(anyhow!("no address for ipfix collector {addr}"))?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).context("bind ipfix socket")?;
+        socket
+            .connect(addr)
+            .with_context(|| format!("connect to ipfix collector {addr}"))?;
+        Ok(Ipfix {
+            socket,
+            domain,
+            sequence: 0,
+            addresses: cfg!(not(feature = "privacy")),
+        })
+    }
+
+    /// whether records carry the source and destination addresses, in the clear
+    pub fn addresses(mut self, enabled: bool) -> Self {
+        self.addresses = enabled;
+        self
+    }
+
+    /// messages carrying the flows, each starting with the templates
+    pub fn messages(&mut self, flows: &[Flow], export_time: u32) -> Vec<Vec<u8>> {
+        let templates = if self.addresses {
+            vec![
+                template(TEMPLATE_V4, &V4_FIELDS),
+                template(TEMPLATE_V6, &V6_FIELDS),
+            ]
+        } else {
+            vec![template(TEMPLATE_PORTS, &[])]
+        };
+        let templates = set(TEMPLATE_SET_ID, &templates);
+        let mut records: Vec<(u16, Vec<u8>)> = flows
+            .iter()
+            .map(|flow| record(flow, self.addresses))
+            .collect();
+        records.sort_by_key(|(template, _)| *template);
+
+        let mut messages = Vec::new();
+        let mut sets: Vec<(u16, Vec<Vec<u8>>)> = Vec::new();
+        let mut size = HEADER_LEN + templates.len();
+        for (template, record) in records {
+            let set_header = |sets: &[(u16, Vec<Vec<u8>>)]| match sets.last() {
+                Some((t, _)) if *t == template => 0,
+                _ => 4,
+            };
+            if size + set_header(&sets) + record.len() > MAX_MESSAGE && !sets.is_empty() {
+                messages.push(self.message(&templates, std::mem::take(&mut sets), export_time));
+                size = HEADER_LEN + templates.len();
+            }
+            size += set_header(&sets) + record.len();
+            match sets.last_mut() {
+                Some((t, records)) if *t == template => records.push(record),
+                _ => sets.push((template, vec![record])),
+            }
+        }
+        if !sets.is_empty() {
+            messages.push(self.message(&templates, sets, export_time));
+        }
+        messages
+    }
+
+    /// the sequence number of a message counts the data records sent before it
+    fn message(
+        &mut self,
+        templates: &[u8],
+        sets: Vec<(u16, Vec<Vec<u8>>)>,
+        export_time: u32,
+    ) -> Vec<u8> {
+        let mut body = templates.to_vec();
+        let mut count = 0;
+        for (template, records) in sets {
+            count += records.len() as u32;
+            body.extend(set(template, &records));
+        }
+
+        let mut message = Vec::with_capacity(HEADER_LEN + body.len());
+        message.extend_from_slice(&VERSION.to_be_bytes());
+        message.extend_from_slice(&((HEADER_LEN + body.len()) as u16).to_be_bytes());
+        message.extend_from_slice(&export_time.to_be_bytes());
+        message.extend_from_slice(&self.sequence.to_be_bytes());
+        message.extend_from_slice(&self.domain.to_be_bytes());
+        message.extend(body);
+        self.sequence = self.sequence.wrapping_add(count);
+        message
+    }
+}
+
+impl Sink for Ipfix {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let export_time = unix_now().as_secs() as u32;
+        for message in self.messages(&digest.flows, export_time) {
+            self.socket
+                .send(&message)
+                .context("send to ipfix collector")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use crate::aggregate::Digest;
+    use crate::capture::Direction;
+    use crate::collector::{ConnectionKey, Flow};
+    use crate::ipfix::Ipfix;
+    use crate::output::Sink;
+    use crate::sensitive::IpAddress;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes([data[offset], data[offset + 1]])
+    }
+
+    type Templates = HashMap<u16, Vec<(u16, u16)>>;
+
+    /// decodes a message into its templates and data records, keyed by template id
+    fn decode(message: &[u8]) -> (Templates, Vec<(u16, Vec<u8>)>) {
+        assert_eq!(u16_at(message, 0), 10);
+        assert_eq!(u16_at(message, 2) as usize, message.len());
+
+        let mut templates = HashMap::new();
+        let mut records = Vec::new();
+        let mut offset = 16;
+        while offset < message.len() {
+            let (id, length) = (
+                u16_at(message, offset),
+                u16_at(message, offset + 2) as usize,
+            );
+            let set = &message[offset + 4..offset + length];
+            if id == 2 {
+                let mut at = 0;
+                while at < set.len() {
+                    let (template, count) = (u16_at(set, at), u16_at(set, at + 2));
+                    let fields = (0..count as usize)
+                        .map(|i| (u16_at(set, at + 4 + i * 4), u16_at(set, at + 6 + i * 4)))
+                        .collect::<Vec<_>>();
+                    at += 4 + fields.len() * 4;
+                    templates.insert(template, fields);
+                }
+            } else {
+                let size: usize = templates[&id].iter().map(|&(_, l)| l as usize).sum();
+                records.extend(set.chunks(size).map(|r| (id, r.to_vec())));
+            }
+            offset += length;
+        }
+        (templates, records)
+    }
+
+    fn flow(source: IpAddress, port: u16, count: u64) -> Flow {
+        Flow {
+            key: ConnectionKey {
+                direction: Direction::In,
+                source_ip: Some(source),
+                dest_ip: None,
+                dest_port: Some(port),
+            },
+            count,
+            first: Duration::from_millis(1697723025123),
+            last: Duration::from_millis(1697723026456),
+        }
+    }
+
+    #[test]
+    fn test_export_decodes() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+
+        let v6 = IpAddress::V6([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let digest = Digest {
+            flows: vec![flow(IpAddress::V4([192, 0, 2, 1]), 22, 3), flow(v6, 443, 1)],
+            ..Default::default()
+        };
+        let mut ipfix = Ipfix::connect(&addr, 7).unwrap().addresses(true);
+        ipfix.digest(&digest).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let len = server.recv(&mut buf).unwrap();
+        let (templates, records) = decode(&buf[..len]);
+
+        assert_eq!(templates[&256][0], (8, 4));
+        assert_eq!(templates[&257][0], (27, 16));
+        assert_eq!(templates[&256].len(), 8);
+        assert_eq!(records.len(), 2);
+
+        let (template, v4_record) = &records[0];
+        assert_eq!(*template, 256);
+        assert_eq!(&v4_record[..4], &[192, 0, 2, 1]);
+        assert_eq!(&v4_record[4..8], &[0, 0, 0, 0]);
+        assert_eq!(u16_at(v4_record, 8), 22);
+        assert_eq!(v4_record[10], 6);
+        assert_eq!(&v4_record[12..20], &1697723025123u64.to_be_bytes());
+        assert_eq!(&v4_record[28..36], &3u64.to_be_bytes());
+
+        assert_eq!(records[1].0, 257);
+        assert_eq!(u16_at(&records[1].1, 32), 443);
+    }
+
+    #[test]
+    fn test_export_without_addresses() {
+        let mut ipfix = Ipfix::connect("127.0.0.1:4739", 0)
+            .unwrap()
+            .addresses(false);
+        let flows = [flow(IpAddress::V4([192, 0, 2, 1]), 22, 3)];
+        let messages = ipfix.messages(&flows, 0);
+        let (templates, records) = decode(&messages[0]);
+
+        assert_eq!(templates.keys().collect::<Vec<_>>(), [&258]);
+        assert_eq!(templates[&258].len(), 6);
+        assert_eq!(records.len(), 1);
+        let (template, record) = &records[0];
+        assert_eq!(*template, 258);
+        assert_eq!(u16_at(record, 0), 22);
+        assert_eq!(&record[20..28], &3u64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_messages_split_with_sequence() {
+        let mut ipfix = Ipfix::connect("127.0.0.1:4739", 0).unwrap();
+        let flows: Vec<Flow> = (0..100)
+            .map(|i| flow(IpAddress::V4([192, 0, 2, i]), 22, 1))
+            .collect();
+        let messages = ipfix.messages(&flows, 0);
+        assert_eq!(messages.len(), 3);
+
+        let mut sequence = 0;
+        for message in &messages {
+            assert!(message.len() <= 1400);
+            assert_eq!(
+                u32::from_be_bytes(message[8..12].try_into().unwrap()),
+                sequence
+            );
+            sequence += decode(message).1.len() as u32;
+        }
+        assert_eq!(sequence, 100);
+    }
+}
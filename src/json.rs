@@ -0,0 +1,73 @@
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+pub(crate) fn get<'a>(value: &'a Value, key: &str) -> Result<&'a Value> {
+    value.get(key).ok_or(anyhow!("missing {key}"))
+}
+
+pub(crate) fn number(value: &Value) -> Result<u64> {
+    value
+        .as_u64()
+        .ok_or(anyhow!("expected a number, found {value}"))
+}
+
+/// a time or duration in nanoseconds, which fits in a u64 until 2554
+pub(crate) fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos() as u64
+}
+
+pub(crate) fn port(value: &Value) -> Result<u16> {
+    Ok(u16::try_from(number(value)?)?)
+}
+
+pub(crate) fn optional<T>(value: &Value, parse: impl Fn(&Value) -> Result<T>) -> Result<Option<T>> {
+    match value {
+        Value::Null => Ok(None),
+        value => parse(value).map(Some),
+    }
+}
+
+pub(crate) fn text(value: &Value) -> Result<&str> {
+    value
+        .as_str()
+        .ok_or(anyhow!("expected a string, found {value}"))
+}
+
+pub(crate) fn array(value: &Value) -> Result<&Vec<Value>> {
+    value
+        .as_array()
+        .ok_or(anyhow!("expected an array, found {value}"))
+}
+
+/// the values of an array of fixed length
+pub(crate) fn tuple<const N: usize>(value: &Value) -> Result<[&Value; N]> {
+    let values: Vec<&Value> = array(value)?.iter().collect();
+    values
+        .try_into()
+        .map_err(|_| anyhow!("expected {N} values, found {value}"))
+}
+
+/// the variant of an enum which is displayed as this value
+pub(crate) fn variant<T: fmt::Display + Copy>(all: &[T], value: &Value) -> Result<T> {
+    let name = text(value)?;
+    all.iter()
+        .copied()
+        .find(|variant| variant.to_string() == name)
+        .ok_or(anyhow!("unknown value: {name}"))
+}
+
+/// the values of each element of an array, with the index of a bad element in the error
+pub(crate) fn each<T>(
+    value: &Value,
+    key: &str,
+    parse: impl Fn(&Value) -> Result<T>,
+) -> Result<Vec<T>> {
+    array(get(value, key)?)?
+        .iter()
+        .enumerate()
+        .map(|(i, element)| parse(element).with_context(|| format!("{key}[{i}]")))
+        .collect()
+}
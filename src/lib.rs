@@ -1,4 +1,33 @@
+pub mod aggregate;
+pub mod alert;
+pub mod blocklist;
 pub mod capture;
+pub mod checkpoint;
 pub mod collector;
+pub mod control;
+pub mod diff;
+pub mod events;
+pub mod fingerprint;
+pub mod fleet;
+pub mod flood;
+pub mod graphite;
+pub mod hooks;
+pub mod http;
+pub mod influx;
+pub mod ipfix;
+pub mod json;
+pub mod otlp;
+pub mod output;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod parse;
+pub mod portscan;
+pub mod rrd;
+pub mod rules;
 pub mod runtime;
+pub mod scanner;
 pub mod sensitive;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod statsd;
+pub mod time;
@@ -1,40 +1,260 @@
-use std::io::{stdout, BufWriter};
+use std::fs;
+use std::io::{stdout, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use anyhow::Error;
-use clap::Parser;
+use anyhow::{anyhow, Context, Error, Result};
+use clap::{Parser, Subcommand};
 use color_print::cstr;
-use log::{debug, error};
+use log::{debug, error, info};
 
-use pulso::collector::Collector;
-use pulso::runtime::collect_async;
+use pulso::aggregate::{DigestFormat, GroupBy};
+use pulso::alert::AlertOutputs;
+use pulso::blocklist::{Blocklist, Format};
+use pulso::capture::{Direction, Directions};
+use pulso::checkpoint::Checkpoint;
+use pulso::collector::{unix_now, Collector, Dimension, KeySpec};
+use pulso::control::{self, ControlSocket};
+use pulso::diff::{Diff, DiffFormat, Summary};
+use pulso::events::{EventLog, EventTarget};
+use pulso::fleet::{key_from_env, serve, Fleet};
+use pulso::flood::SynFloodDetector;
+use pulso::hooks::{Action, Hook, Throttle};
+use pulso::http::Url;
+use pulso::output::{OutputOptions, Outputs, Target};
+use pulso::parse::DigestFile;
+use pulso::portscan::PortScanDetector;
+use pulso::rrd::{Consolidation, Resolution, RoundRobin, Rrd};
+use pulso::rules::{load_rules, RuleDetector};
+use pulso::runtime::{collect_async, run_periodic, Stopped};
 
 /// TCP connection counter
 #[derive(Parser, Debug)]
 #[command(author, version, about, after_help = AFTER_HELP)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// device name
-    #[arg(short, long)]
-    device: String,
+    #[arg(short, long, required = true)]
+    device: Option<String>,
     /// max connections
     #[arg(short, long)]
     connection_limit: Option<u64>,
     /// max seconds
     #[arg(short, long)]
     time_limit: Option<u64>,
+    /// connections to count: in, out, both
+    #[arg(short = 'D', long, default_value = "in")]
+    direction: Directions,
+    /// dimensions to count by: src, dst, dport [default: src,dport inbound, dst,dport outbound]
+    #[arg(short, long)]
+    key: Option<KeySpec>,
+    /// digest grouping, repeat for multiple views [default: same as key]
+    #[arg(short, long)]
+    group_by: Vec<GroupBy>,
+    /// count TCP SYN fingerprints per port
+    #[arg(short, long)]
+    fingerprint: bool,
+    /// count connections from known mass scanners per port
+    #[arg(short, long)]
+    scanners: bool,
+    /// alert when a source connects to more than this many ports within the scan window
+    #[arg(long)]
+    scan_ports: Option<u64>,
+    /// alert when a source connects to more than this many hosts on one port within the scan window
+    #[arg(long)]
+    scan_hosts: Option<u64>,
+    /// port scan sliding window in seconds
+    #[arg(long, default_value_t = 60)]
+    scan_window: u64,
+    /// alert when the SYNs to a port in a flood interval exceed its baseline by this factor
+    #[arg(long)]
+    flood_factor: Option<f64>,
+    /// alert when more than this fraction of the SYNs to a port in a flood interval were unanswered
+    #[arg(long)]
+    flood_unanswered: Option<f64>,
+    /// SYN rate interval in seconds
+    #[arg(long, default_value_t = 10)]
+    flood_interval: u64,
+    /// SYNs per flood interval below which no flood alert is raised
+    #[arg(long, default_value_t = 100)]
+    flood_min: u64,
+    /// produce a digest every this many seconds, instead of once at the end
+    #[arg(short, long)]
+    interval: Option<u64>,
+    /// where to send the digest and alerts: stdout, syslog, journald, file:<path>, statsd:<addr>, dogstatsd:<addr>, influx[:<path>|:<url>], graphite:<addr>, ipfix:<addr>, otlp:<url>, sqlite:<path>, rrd:<dir>, parquet:<dir>, agent:<addr>. repeat for multiple [default: stdout]
+    #[arg(short, long)]
+    output: Vec<Target>,
+    /// digest format for stdout and file outputs: text, csv (inbound connections per source and port)
+    #[arg(long, default_value = "text")]
+    format: DigestFormat,
+    /// days of windows kept by a sqlite output [default: keep all]
+    #[arg(long)]
+    retention: Option<u64>,
+    /// rows per row group in a parquet output
+    #[arg(long, default_value_t = 16384)]
+    parquet_row_group: usize,
+    /// seconds covered by each file of a parquet output
+    #[arg(long, default_value_t = 3600)]
+    parquet_rotate: u64,
+    /// include real addresses in ipfix records, even with the privacy feature
+    #[arg(long)]
+    ipfix_addresses: bool,
+    /// ports archived by rrd outputs, separated by commas
+    #[arg(long, value_delimiter = ',')]
+    rrd_ports: Vec<u16>,
+    /// append alerts to a file
+    #[arg(short, long)]
+    alert_file: Option<PathBuf>,
+    /// threshold rules with actions, one per line
+    #[arg(short = 'r', long)]
+    alert_rules: Option<PathBuf>,
+    /// run a command for every alert, with the alert as JSON on stdin. arguments are split on whitespace
+    #[arg(long, allow_hyphen_values = true)]
+    alert_exec: Option<String>,
+    /// POST every alert as JSON to an http:// URL
+    #[arg(long)]
+    alert_webhook: Option<Url>,
+    /// seconds during which a repeated alert does not trigger actions again
+    #[arg(long, default_value_t = 300)]
+    alert_quiet: u64,
+    /// maximum number of alerts per minute which trigger each action
+    #[arg(long, default_value_t = 10)]
+    alert_rate: usize,
+    /// write sources above the blocklist threshold to a firewall set file, with real addresses
+    #[arg(short, long)]
+    blocklist: Option<PathBuf>,
+    /// blocklist file format: nft, ipset
+    #[arg(long, default_value = "nft")]
+    blocklist_format: Format,
+    /// blocklist set name, suffixed with 4 and 6
+    #[arg(long, default_value = "pulso")]
+    blocklist_name: String,
+    /// inbound connections from a source above which it is blocklisted
+    #[arg(long, default_value_t = 100)]
+    blocklist_threshold: u64,
+    /// seconds until a blocklisted source expires from the set
+    #[arg(long)]
+    blocklist_timeout: Option<u64>,
+    /// write a line per connection to a file or to "syslog"
+    #[arg(short, long)]
+    events: Option<EventTarget>,
+    /// write one in this many connection events
+    #[arg(long, default_value_t = 1)]
+    events_sample: u64,
+    /// include real source addresses in connection events, even with the privacy feature
+    #[arg(long)]
+    events_addresses: bool,
+    /// save the counts of the current window to a file, so that a restart can resume it
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// seconds between checkpoints, which are also saved after each digest and on SIGINT or SIGTERM
+    #[arg(long, default_value_t = 60)]
+    checkpoint_interval: u64,
+    /// allow checkpoints to hold real addresses, even with the privacy feature
+    #[arg(long)]
+    checkpoint_addresses: bool,
+    /// continue the window saved in a checkpoint file, if it exists
+    #[arg(long)]
+    resume: Option<PathBuf>,
+    /// accept `pulso ctl` requests on a Unix socket, which only the owner can connect to
+    #[arg(long)]
+    control: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// print per-port counters from the archives written by `-o rrd:<dir>`
+    Query {
+        /// archive directory
+        dir: PathBuf,
+        /// destination port
+        #[arg(short, long)]
+        port: u16,
+        /// direction of the connections: in, out
+        #[arg(short = 'D', long, default_value = "in")]
+        direction: Direction,
+        /// row size: minute, hour, day
+        #[arg(short, long, default_value = "minute")]
+        resolution: Resolution,
+        /// consolidation function: sum, max (busiest minute), average (per minute)
+        #[arg(short, long, default_value = "sum")]
+        consolidation: Consolidation,
+        /// first unix time to print [default: oldest row]
+        #[arg(long)]
+        start: Option<u64>,
+        /// last unix time to print [default: now]
+        #[arg(long)]
+        end: Option<u64>,
+    },
+    /// sum digests in the text or csv format, from several hosts or runs, into one
+    Merge {
+        /// digest files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// compare two digests in the same format: new and vanished sources, and the change in connections per port
+    Diff {
+        /// earlier digest file
+        before: PathBuf,
+        /// later digest file
+        after: PathBuf,
+        /// output format: text, json
+        #[arg(short, long, default_value = "text")]
+        format: DiffFormat,
+    },
+    /// merge the windows reported by agents with `-o agent:<addr>`, and send the fleet-wide digest
+    /// to outputs
+    Server {
+        /// TCP address to accept agents on
+        #[arg(short, long)]
+        listen: String,
+        /// send the merged digest every this many seconds
+        #[arg(short, long, default_value_t = 60)]
+        interval: u64,
+        /// where to send the merged digest, as for the capture except ipfix, sqlite and parquet,
+        /// which need connection records [default: stdout]
+        #[arg(short, long)]
+        output: Vec<Target>,
+        /// digest format for stdout and file outputs: text
+        #[arg(long, default_value = "text")]
+        format: DigestFormat,
+    },
+    /// query a running capture through its `--control` socket
+    Ctl {
+        /// control socket
+        #[arg(short, long)]
+        socket: PathBuf,
+        /// snapshot, top [<n>], reset or stats
+        #[arg(required = true)]
+        request: Vec<String>,
+    },
 }
 
 #[cfg(feature = "privacy")]
 const AFTER_HELP: Option<&str> = Some(cstr!(
     r#"<bold><underline>Environment Variables:</underline></bold>
-  PULSO_SECRET    (required) encryption key used for sensitive information
+  PULSO_SECRET       (required) encryption key used for sensitive information
+  PULSO_FLEET_KEY    key signing the reports of agent outputs to a server
 "#
 ));
 #[cfg(not(feature = "privacy"))]
-const AFTER_HELP: Option<&str> = None;
+const AFTER_HELP: Option<&str> = Some(cstr!(
+    r#"<bold><underline>Environment Variables:</underline></bold>
+  PULSO_FLEET_KEY    key signing the reports of agent outputs to a server
+"#
+));
 
 impl Args {
     fn parse() -> Self {
         let args = <Self as Parser>::parse();
+        if args.command.is_some() {
+            return args;
+        }
         assert!(
             args.connection_limit
                 .filter(|&l| l == 0)
@@ -42,10 +262,22 @@ impl Args {
                 .is_none(),
             "limits must be positive",
         );
+        assert!(
+            args.scan_window > 0
+                && args.flood_interval > 0
+                && args.interval != Some(0)
+                && args.checkpoint_interval > 0,
+            "intervals must be positive",
+        );
+        assert!(args.events_sample > 0, "events sample must be positive");
+        assert!(
+            args.parquet_row_group > 0 && args.parquet_rotate > 0,
+            "parquet row group and rotation must be positive"
+        );
 
         #[cfg(feature = "privacy")]
         assert!(
-            std::env::var("PULSO_SECRET").map_or(false, |s| !s.is_empty()),
+            std::env::var("PULSO_SECRET").is_ok_and(|s| !s.is_empty()),
             "Environment variable PULSO_SECRET must be non-empty"
         );
 
@@ -63,30 +295,367 @@ fn report_error(error: Error, msg: &str) {
     );
 }
 
+fn or_exit<T>(result: Result<T>, msg: &str, code: i32) -> T {
+    result.unwrap_or_else(|e| {
+        report_error(e, msg);
+        std::process::exit(code);
+    })
+}
+
+fn run(command: &Command) {
+    match command {
+        Command::Query {
+            dir,
+            port,
+            direction,
+            resolution,
+            consolidation,
+            start,
+            end,
+        } => {
+            let archive = or_exit(
+                RoundRobin::open(&Rrd::path(dir, *direction, *port)),
+                "failed to open archive",
+                1,
+            );
+            let end = end.unwrap_or(unix_now().as_secs());
+            let rows = or_exit(
+                archive.fetch(*resolution, *consolidation, start.unwrap_or(0), end),
+                "failed to read archive",
+                1,
+            );
+            for (bucket, value) in rows {
+                println!("{bucket} {value}");
+            }
+        }
+        Command::Merge { files } => {
+            let mut merged: Option<DigestFile> = None;
+            for path in files {
+                let digest = read_digest(path);
+                match &mut merged {
+                    Some(merged) => or_exit(merged.merge(&digest), "failed to merge digests", 2),
+                    None => merged = Some(digest),
+                }
+            }
+            if let Some(merged) = merged {
+                or_exit(
+                    merged.write(&mut stdout().lock()),
+                    "failed to write digest",
+                    1,
+                );
+            }
+        }
+        Command::Diff {
+            before,
+            after,
+            format,
+        } => {
+            let (before, after) = (read_digest(before), read_digest(after));
+            if matches!(before, DigestFile::Text(_)) != matches!(after, DigestFile::Text(_)) {
+                report_error(
+                    anyhow!("cannot compare text and csv digests"),
+                    "invalid digest",
+                );
+                std::process::exit(2);
+            }
+            let diff = Diff::new(&Summary::of(&before), &Summary::of(&after));
+            let mut out = stdout().lock();
+            let written = match format {
+                DiffFormat::Text => diff.write(&mut out),
+                DiffFormat::Json => writeln!(out, "{}", diff.to_json()).map_err(Error::from),
+            };
+            or_exit(written, "failed to write diff", 1);
+        }
+        Command::Server {
+            listen,
+            interval,
+            output,
+            format,
+        } => {
+            if *interval == 0 {
+                report_error(anyhow!("--interval must be positive"), "invalid arguments");
+                std::process::exit(2);
+            }
+            // reports carry the tables of each window, not its connection records
+            if output.iter().any(Target::needs_flows) {
+                report_error(
+                    anyhow!("ipfix, sqlite and parquet outputs need connection records, which reports don't carry"),
+                    "invalid output",
+                );
+                std::process::exit(2);
+            }
+            if *format == DigestFormat::Csv {
+                report_error(
+                    anyhow!("csv digests need connection records, which reports don't carry"),
+                    "invalid format",
+                );
+                std::process::exit(2);
+            }
+            let key = or_exit(key_from_env(), "missing fleet key", 2);
+            let listener = or_exit(
+                TcpListener::bind(listen).with_context(|| format!("listen on {listen}")),
+                "failed to start server",
+                1,
+            );
+            info!("accepting agents on {listen}");
+            let fleet = Arc::new(Mutex::new(Fleet::default()));
+            let server_fleet = fleet.clone();
+            thread::spawn(move || serve(listener, key, server_fleet));
+
+            let options = OutputOptions {
+                device: "fleet".to_string(),
+                format: *format,
+                ..Default::default()
+            };
+            let targets = match output.is_empty() {
+                true => vec![Target::Stdout],
+                false => output.clone(),
+            };
+            let mut outputs = Outputs::default();
+            for target in &targets {
+                outputs = outputs.sink(or_exit(target.open(&options), "failed to open output", 1));
+            }
+            let mut send = || {
+                let digest = fleet.lock().unwrap().take();
+                if let Some(digest) = digest {
+                    // failing outputs are logged, and the next window is sent regardless
+                    let _ = outputs.digest(&digest);
+                }
+            };
+            or_exit(
+                run_periodic(Duration::from_secs(*interval), &mut send),
+                "failed to run server",
+                1,
+            );
+            // reports received since the last window
+            send();
+            or_exit(outputs.close(), "failed to close outputs", 1);
+        }
+        Command::Ctl { socket, request } => {
+            let response = or_exit(
+                control::request(socket, &request.join(" ")),
+                "control request failed",
+                1,
+            );
+            print!("{response}");
+        }
+    }
+}
+
+fn read_digest(path: &Path) -> DigestFile {
+    let input = or_exit(
+        fs::read_to_string(path).with_context(|| format!("read {}", path.display())),
+        "failed to read digest",
+        1,
+    );
+    or_exit(
+        DigestFile::parse(&input).with_context(|| format!("parse {}", path.display())),
+        "invalid digest",
+        2,
+    )
+}
+
 fn main() {
     env_logger::init();
     debug!("main");
 
     let args = Args::parse();
+    if let Some(command) = &args.command {
+        run(command);
+        return;
+    }
+    let device = args.device.as_deref().expect("device is required");
 
-    let mut collector = Collector::default();
+    let mut collector = Collector::new(args.direction, args.key)
+        .views(args.group_by.clone())
+        .interval(args.interval.map(Duration::from_secs))
+        .fingerprints(args.fingerprint)
+        .scanners(args.scanners);
+    if args.scan_ports.is_some() || args.scan_hosts.is_some() {
+        collector = collector.detector(PortScanDetector::new(
+            Duration::from_secs(args.scan_window),
+            args.scan_ports,
+            args.scan_hosts,
+        ));
+    }
+    if args.flood_factor.is_some() || args.flood_unanswered.is_some() {
+        collector = collector.detector(SynFloodDetector::new(
+            Duration::from_secs(args.flood_interval),
+            args.flood_factor,
+            args.flood_unanswered,
+            args.flood_min,
+        ));
+    }
+    if let Some(target) = &args.events {
+        let events = or_exit(
+            EventLog::open(target, device, args.events_sample),
+            "failed to open event output",
+            1,
+        );
+        let events = events.addresses(args.events_addresses || cfg!(not(feature = "privacy")));
+        collector = collector.events(events);
+    }
+    or_exit(collector.validate(), "invalid digest grouping", 2);
+    if let Some(path) = &args.resume {
+        let state = or_exit(
+            Checkpoint::load(path).with_context(|| format!("load {}", path.display())),
+            "invalid checkpoint",
+            2,
+        );
+        match state {
+            Some(state) => {
+                or_exit(collector.restore(state), "failed to resume checkpoint", 2);
+                info!("resumed checkpoint {}", path.display());
+            }
+            None => info!("no checkpoint at {}, starting a new window", path.display()),
+        }
+    }
+    if args.checkpoint.is_some() && cfg!(feature = "privacy") && !args.checkpoint_addresses {
+        report_error(
+            anyhow!("checkpoints hold real addresses unless --checkpoint-addresses is given"),
+            "invalid checkpoint",
+        );
+        std::process::exit(2);
+    }
+    let checkpoint = args
+        .checkpoint
+        .as_ref()
+        .map(|path| Checkpoint::new(path).interval(Duration::from_secs(args.checkpoint_interval)));
+
+    let mut outputs = Outputs::default();
+    if let Some(path) = &args.blocklist {
+        let counted = collector.directions().list().contains(&Direction::In)
+            && collector.key(Direction::In).contains(Dimension::Source);
+        if !counted {
+            report_error(
+                anyhow!("inbound connections must be counted by src"),
+                "invalid blocklist",
+            );
+            std::process::exit(2);
+        }
+        outputs = outputs.blocklist(Blocklist::new(
+            path,
+            args.blocklist_format,
+            &args.blocklist_name,
+            args.blocklist_threshold,
+            args.blocklist_timeout.map(Duration::from_secs),
+        ));
+    }
+
+    if args.format == DigestFormat::Csv && !collector.directions().list().contains(&Direction::In) {
+        report_error(
+            anyhow!("csv digests count inbound connections, which are not monitored"),
+            "invalid digest format",
+        );
+        std::process::exit(2);
+    }
+
+    let targets = if args.output.is_empty() {
+        vec![Target::Stdout]
+    } else {
+        args.output.clone()
+    };
+    let archived = targets.iter().any(|t| matches!(t, Target::Rrd(_)));
+    if archived && !args.interval.is_some_and(|i| i <= 60) {
+        report_error(
+            anyhow!("archives need a window of at most 60 seconds"),
+            "invalid rrd output",
+        );
+        std::process::exit(2);
+    }
+    if archived && args.rrd_ports.is_empty() {
+        report_error(
+            anyhow!("archives need the ports to keep, with --rrd-ports"),
+            "invalid rrd output",
+        );
+        std::process::exit(2);
+    }
+    let options = OutputOptions {
+        device: device.to_string(),
+        retention: args.retention.map(|days| Duration::from_secs(days * 86400)),
+        row_group: args.parquet_row_group,
+        rotate: Duration::from_secs(args.parquet_rotate),
+        format: args.format,
+        ipfix_addresses: args.ipfix_addresses || cfg!(not(feature = "privacy")),
+        rrd_ports: args.rrd_ports.clone(),
+    };
+    for target in &targets {
+        outputs = outputs.sink(or_exit(target.open(&options), "failed to open output", 1));
+    }
 
-    if let Err(e) = collect_async(
-        &args.device,
+    let mut alerts = AlertOutputs::default();
+    if let Some(path) = &args.alert_file {
+        alerts = or_exit(alerts.file(path), "failed to open alert output", 1);
+    }
+
+    let throttle = Throttle::new(Duration::from_secs(args.alert_quiet), args.alert_rate);
+    let global_actions = [
+        args.alert_exec
+            .as_deref()
+            .map(|command| command.split_whitespace().map(String::from).collect())
+            .filter(|command: &Vec<String>| !command.is_empty())
+            .map(Action::Exec),
+        args.alert_webhook.clone().map(Action::Webhook),
+    ];
+    for action in global_actions.into_iter().flatten() {
+        alerts = alerts.hook(Hook::new(None, action, throttle.clone()));
+    }
+    if let Some(path) = &args.alert_rules {
+        let rules = or_exit(load_rules(path), "failed to load alert rules", 2);
+        for (index, rule) in rules.iter().enumerate() {
+            for action in &rule.actions {
+                let hook = Hook::new(Some(index), action.clone(), throttle.clone());
+                alerts = alerts.hook(hook);
+            }
+        }
+        collector = collector.detector(RuleDetector::new(rules));
+    }
+    outputs = outputs.alerts(alerts);
+
+    let control = args.control.as_ref().map(|path| {
+        or_exit(
+            ControlSocket::bind(path),
+            "failed to open control socket",
+            1,
+        )
+    });
+
+    let collected = collect_async(
+        device,
         args.connection_limit,
         args.time_limit,
         &mut collector,
-    ) {
-        report_error(e, "failed to start capture stream");
-        std::process::exit(1);
+        &mut outputs,
+        checkpoint.as_ref(),
+        control.as_ref(),
+    );
+    outputs.finish();
+    let stopped = match collected {
+        Ok(stopped) => stopped,
+        Err(e) => {
+            // completes files of earlier windows, such as parquet files still being written
+            let _ = outputs.close();
+            report_error(e, "failed to start capture stream");
+            std::process::exit(1);
+        }
+    };
+
+    if let (Stopped::Interrupted, Some(checkpoint)) = (stopped, &checkpoint) {
+        // the window is left open, to be continued with --resume
+        or_exit(checkpoint.save(&collector), "failed to save checkpoint", 1);
+        or_exit(outputs.close(), "failed to close outputs", 1);
+        return;
     }
 
     debug!("stream finished. creating digest");
 
-    let mut writer = BufWriter::new(stdout());
-
-    if let Err(e) = collector.digest(&mut writer) {
+    let flushed = outputs.flush(&mut collector);
+    if let Err(e) = flushed.and(outputs.close()) {
         report_error(e, "failed to write digest output");
         std::process::exit(1);
     }
+    if let Some(checkpoint) = &checkpoint {
+        // the counts were reported, so a later --resume starts from the empty window
+        or_exit(checkpoint.save(&collector), "failed to save checkpoint", 1);
+    }
 }
@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::aggregate::Digest;
+use crate::http::{self, Url};
+use crate::output::{hostname, Sink};
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const SCOPE: &str = "pulso";
+/// AGGREGATION_TEMPORALITY_DELTA, as each window is counted from zero
+const DELTA: u8 = 1;
+
+fn attribute(key: &str, value: Value) -> Value {
+    match value {
+        Value::Number(n) => json!({ "key": key, "value": { "intValue": n.to_string() } }),
+        value => json!({ "key": key, "value": { "stringValue": value } }),
+    }
+}
+
+fn point(digest: &Digest, value: u64, attributes: Vec<Value>) -> Value {
+    json!({
+        "startTimeUnixNano": digest.start.as_nanos().to_string(),
+        "timeUnixNano": digest.end.as_nanos().to_string(),
+        "asInt": value.to_string(),
+        "attributes": attributes,
+    })
+}
+
+fn counter(name: &str, description: &str, points: Vec<Value>) -> Value {
+    json!({
+        "name": name,
+        "description": description,
+        "unit": "1",
+        "sum": {
+            "dataPoints": points,
+            "aggregationTemporality": DELTA,
+            "isMonotonic": true,
+        },
+    })
+}
+
+/// an ExportMetricsServiceRequest in the OTLP/JSON encoding, with the measurements of a window:
+///
+/// - `pulso.connections` per direction and port, a delta sum
+/// - `pulso.sources`, a gauge of distinct sources
+/// - `pulso.pcap.dropped`, a delta sum
+pub fn request(digest: &Digest, resource: &[(&str, &str)]) -> Value {
+    let connections = digest
+        .ports
+        .iter()
+        .map(|(direction, port, count)| {
+            let attributes = vec![
+                attribute("direction", json!(direction.to_string())),
+                attribute("port", json!(port)),
+            ];
+            point(digest, *count, attributes)
+        })
+        .collect();
+    let metrics = vec![
+        counter("pulso.connections", "new TCP connections", connections),
+        json!({
+            "name": "pulso.sources",
+            "description": "distinct source addresses",
+            "unit": "1",
+            "gauge": { "dataPoints": [point(digest, digest.sources, vec![])] },
+        }),
+        counter(
+            "pulso.pcap.dropped",
+            "packets dropped by the capture",
+            vec![point(digest, digest.dropped, vec![])],
+        ),
+    ];
+    let resource: Vec<Value> = resource
+        .iter()
+        .map(|(key, value)| attribute(key, json!(value)))
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": { "attributes": resource },
+            "scopeMetrics": [{
+                "scope": { "name": SCOPE, "version": env!("CARGO_PKG_VERSION") },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+/// OTLP/HTTP metrics in the JSON encoding, POSTed to a collector's `/v1/metrics` endpoint
+pub struct Otlp {
+    url: Url,
+    host: String,
+    device: String,
+}
+
+impl Otlp {
+    pub fn new(url: Url, device: &str) -> Self {
+        Otlp {
+            url,
+            host: hostname(),
+            device: device.to_string(),
+        }
+    }
+
+    fn resource(&self) -> [(&str, &str); 4] {
+        [
+            ("service.name", "pulso"),
+            ("service.version", env!("CARGO_PKG_VERSION")),
+            ("host.name", &self.host),
+            ("network.interface.name", &self.device),
+        ]
+    }
+}
+
+impl Sink for Otlp {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let body = request(digest, &self.resource()).to_string();
+        http::post(&self.url, "application/json", body.as_bytes(), HTTP_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use serde_json::Value;
+
+    use crate::aggregate::Digest;
+    use crate::capture::Direction;
+    use crate::otlp::Otlp;
+    use crate::output::Sink;
+
+    /// accepts one request and returns its body as json
+    fn receiver(listener: TcpListener) -> thread::JoinHandle<(String, Value)> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (request_line, serde_json::from_slice(&body).unwrap())
+        })
+    }
+
+    #[test]
+    fn test_otlp_export() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = receiver(listener);
+
+        let digest = Digest {
+            start: Duration::from_secs(1697723000),
+            end: Duration::from_secs(1697723060),
+            connections: 3,
+            ports: vec![(Direction::In, 22, 3)],
+            sources: 2,
+            dropped: 1,
+            ..Default::default()
+        };
+        let url = format!("http://127.0.0.1:{port}/v1/metrics")
+            .parse()
+            .unwrap();
+        Otlp::new(url, "eth0").digest(&digest).unwrap();
+
+        let (request_line, body) = server.join().unwrap();
+        assert_eq!(request_line, "POST /v1/metrics HTTP/1.1\r\n");
+
+        let resource = &body["resourceMetrics"][0]["resource"]["attributes"];
+        assert_eq!(resource[0]["key"], "service.name");
+        assert_eq!(resource[3]["key"], "network.interface.name");
+        assert_eq!(resource[3]["value"]["stringValue"], "eth0");
+
+        let metrics = &body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        let connections = &metrics[0]["sum"];
+        assert_eq!(metrics[0]["name"], "pulso.connections");
+        assert_eq!(connections["aggregationTemporality"], 1);
+        let point = &connections["dataPoints"][0];
+        assert_eq!(point["asInt"], "3");
+        assert_eq!(point["startTimeUnixNano"], "1697723000000000000");
+        assert_eq!(point["timeUnixNano"], "1697723060000000000");
+        assert_eq!(point["attributes"][0]["value"]["stringValue"], "inbound");
+        assert_eq!(point["attributes"][1]["value"]["intValue"], "22");
+
+        assert_eq!(metrics[1]["name"], "pulso.sources");
+        assert_eq!(metrics[1]["gauge"]["dataPoints"][0]["asInt"], "2");
+        assert_eq!(metrics[2]["name"], "pulso.pcap.dropped");
+        assert_eq!(metrics[2]["sum"]["dataPoints"][0]["asInt"], "1");
+    }
+}
@@ -0,0 +1,735 @@
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{stdout, BufWriter, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error, Result};
+use log::{info, warn};
+
+use crate::aggregate::{Digest, DigestFormat, CSV_HEADER};
+use crate::alert::{Alert, AlertOutputs};
+use crate::blocklist::Blocklist;
+use crate::collector::{unix_now, Collector};
+use crate::fleet::{key_from_env, Agent};
+use crate::graphite::Graphite;
+use crate::http::Url;
+use crate::influx::Influx;
+use crate::ipfix::Ipfix;
+use crate::otlp::Otlp;
+#[cfg(feature = "parquet")]
+use crate::parquet::Parquet;
+use crate::rrd::Rrd;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::Sqlite;
+use crate::statsd::Statsd;
+use crate::time::rfc3339;
+
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// syslog facility daemon
+const FACILITY: u8 = 3;
+/// structured data id, using the enterprise number reserved for documentation
+const SD_ID: &str = "pulso@32473";
+
+/// where digests and alerts are sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Stdout,
+    File(PathBuf),
+    /// RFC 5424 messages to the local syslog socket
+    Syslog,
+    /// structured entries for the systemd journal
+    Journald,
+    /// counters for a StatsD agent at a UDP address
+    Statsd(String),
+    /// counters with tags for a DogStatsD agent at a UDP address
+    DogStatsd(String),
+    /// InfluxDB line protocol to a file, or to stdout
+    Influx(Option<PathBuf>),
+    /// InfluxDB line protocol to an HTTP write endpoint
+    InfluxHttp(Url),
+    /// Graphite plaintext protocol to a TCP address
+    Graphite(String),
+    /// IPFIX flow records to a collector at a UDP address
+    Ipfix(String),
+    /// OTLP/HTTP metrics to an OpenTelemetry collector
+    Otlp(Url),
+    /// windows stored in a SQLite database
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+    /// per-port round-robin archives in a directory
+    Rrd(PathBuf),
+    /// connection records in rotating parquet files in a directory
+    #[cfg(feature = "parquet")]
+    Parquet(PathBuf),
+    /// signed window reports to a `pulso server` at a TCP address
+    Agent(String),
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stdout" => Ok(Target::Stdout),
+            "syslog" => Ok(Target::Syslog),
+            "journald" => Ok(Target::Journald),
+            "influx" => Ok(Target::Influx(None)),
+            _ => match s.split_once(':') {
+                Some(("file", path)) if !path.is_empty() => Ok(Target::File(path.into())),
+                Some(("statsd", addr)) if !addr.is_empty() => Ok(Target::Statsd(addr.into())),
+                Some(("dogstatsd", addr)) if !addr.is_empty() => Ok(Target::DogStatsd(addr.into())),
+                Some(("influx", url)) if url.starts_with("http://") => {
+                    Ok(Target::InfluxHttp(url.parse()?))
+                }
+                Some(("influx", path)) if !path.is_empty() => Ok(Target::Influx(Some(path.into()))),
+                Some(("graphite", addr)) if !addr.is_empty() => Ok(Target::Graphite(addr.into())),
+                Some(("ipfix", addr)) if !addr.is_empty() => Ok(Target::Ipfix(addr.into())),
+                Some(("otlp", url)) => Ok(Target::Otlp(url.parse()?)),
+                #[cfg(feature = "sqlite")]
+                Some(("sqlite", path)) if !path.is_empty() => Ok(Target::Sqlite(path.into())),
+                Some(("rrd", dir)) if !dir.is_empty() => Ok(Target::Rrd(dir.into())),
+                #[cfg(feature = "parquet")]
+                Some(("parquet", dir)) if !dir.is_empty() => Ok(Target::Parquet(dir.into())),
+                Some(("agent", addr)) if !addr.is_empty() => Ok(Target::Agent(addr.into())),
+                _ => Err(anyhow!(
+                    "unknown output: {s} (expected stdout, syslog, journald, file:<path>, \
+                     statsd:<addr>, dogstatsd:<addr>, influx[:<path>|:<url>], graphite:<addr>, ipfix:<addr>, otlp:<url>, sqlite:<path>, rrd:<dir>, parquet:<dir> or agent:<addr>)"
+                )),
+            },
+        }
+    }
+}
+
+/// settings for the outputs which need them
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// reported by outputs which describe their source
+    pub device: String,
+    /// how long outputs which store windows keep them
+    pub retention: Option<Duration>,
+    /// rows per parquet row group
+    pub row_group: usize,
+    /// time covered by each parquet file
+    pub rotate: Duration,
+    /// how digests are written to stdout and files
+    pub format: DigestFormat,
+    /// whether IPFIX records carry addresses, which are real even with the "privacy" feature
+    pub ipfix_addresses: bool,
+    /// ports archived by rrd outputs
+    pub rrd_ports: Vec<u16>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            device: String::new(),
+            retention: None,
+            row_group: 16384,
+            rotate: Duration::from_secs(3600),
+            format: DigestFormat::Text,
+            ipfix_addresses: cfg!(not(feature = "privacy")),
+            rrd_ports: Vec::new(),
+        }
+    }
+}
+
+impl Target {
+    /// whether the output writes connection records, which are not part of fleet reports
+    pub fn needs_flows(&self) -> bool {
+        match self {
+            Target::Ipfix(_) => true,
+            #[cfg(feature = "sqlite")]
+            Target::Sqlite(_) => true,
+            #[cfg(feature = "parquet")]
+            Target::Parquet(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn open(&self, options: &OutputOptions) -> Result<Box<dyn Sink>> {
+        Ok(match self {
+            Target::Stdout => {
+                Box::new(Text::new(BufWriter::new(stdout())).format(options.format, true))
+            }
+            Target::File(path) => {
+                let file = append(path)?;
+                // an existing file already has its header
+                let header = file.metadata()?.len() == 0;
+                Box::new(Text::new(BufWriter::new(file)).format(options.format, header))
+            }
+            Target::Syslog => Box::new(Syslog::connect(SYSLOG_SOCKET)?),
+            Target::Journald => Box::new(Journald::connect(JOURNALD_SOCKET)?),
+            Target::Statsd(addr) => Box::new(Statsd::connect(addr, false)?),
+            Target::DogStatsd(addr) => Box::new(Statsd::connect(addr, true)?),
+            Target::Influx(None) => Box::new(Influx::writer(Box::new(stdout()))),
+            Target::Influx(Some(path)) => {
+                Box::new(Influx::writer(Box::new(BufWriter::new(append(path)?))))
+            }
+            Target::InfluxHttp(url) => Box::new(Influx::http(url.clone())),
+            Target::Graphite(addr) => Box::new(Graphite::new(addr)),
+            Target::Ipfix(addr) => {
+                Box::new(Ipfix::connect(addr, 0)?.addresses(options.ipfix_addresses))
+            }
+            Target::Otlp(url) => Box::new(Otlp::new(url.clone(), &options.device)),
+            #[cfg(feature = "sqlite")]
+            Target::Sqlite(path) => {
+                Box::new(Sqlite::open(path, &options.device, options.retention)?)
+            }
+            Target::Rrd(dir) => Box::new(Rrd::open(dir, &options.rrd_ports)?),
+            #[cfg(feature = "parquet")]
+            Target::Parquet(dir) => Box::new(Parquet::open(dir, options)?),
+            Target::Agent(addr) => Box::new(Agent::new(addr, key_from_env()?, &options.device)),
+        })
+    }
+}
+
+fn append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open output file {}", path.display()))
+}
+
+/// receives a digest at the end of each window, and alerts as they are raised
+pub trait Sink {
+    fn digest(&mut self, digest: &Digest) -> Result<()>;
+
+    fn alert(&mut self, _alert: &Alert) -> Result<()> {
+        Ok(())
+    }
+
+    /// completes output which is held open between windows
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// the digest as text. alerts are left to the log and the alert file
+pub struct Text<W: Write> {
+    out: W,
+    format: DigestFormat,
+    /// whether a CSV header line is still to be written
+    header: bool,
+}
+
+impl<W: Write> Text<W> {
+    pub fn new(out: W) -> Self {
+        Text {
+            out,
+            format: DigestFormat::Text,
+            header: false,
+        }
+    }
+
+    /// writes the digest in a format. the CSV header is written before the first digest if requested
+    pub fn format(mut self, format: DigestFormat, header: bool) -> Self {
+        self.format = format;
+        self.header = header && format == DigestFormat::Csv;
+        self
+    }
+}
+
+impl<W: Write> Sink for Text<W> {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        match self.format {
+            DigestFormat::Text => digest.write(&mut self.out)?,
+            DigestFormat::Csv => {
+                if self.header {
+                    writeln!(self.out, "{CSV_HEADER}")?;
+                    self.header = false;
+                }
+                digest.write_csv(&mut self.out)?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+pub(crate) fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    match (result, std::str::from_utf8(&buf[..len])) {
+        (0, Ok(name)) if !name.is_empty() => name.to_string(),
+        _ => "-".to_string(),
+    }
+}
+
+/// RFC 5424 messages over a unix datagram socket
+pub struct Syslog {
+    socket: UnixDatagram,
+    hostname: String,
+}
+
+impl Syslog {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(path.as_ref())
+            .with_context(|| format!("connect to {}", path.as_ref().display()))?;
+        Ok(Syslog {
+            socket,
+            hostname: hostname(),
+        })
+    }
+
+    /// sends one message. structured data parameters are written in order
+    pub fn send(
+        &self,
+        severity: u8,
+        ts: Duration,
+        msgid: &str,
+        params: &[(&str, String)],
+        msg: &str,
+    ) -> Result<()> {
+        let mut message = format!(
+            "<{}>1 {} {} pulso {} {msgid} ",
+            FACILITY * 8 + severity,
+            rfc3339(ts),
+            self.hostname,
+            std::process::id()
+        );
+        if params.is_empty() {
+            message.push('-');
+        } else {
+            write!(message, "[{SD_ID}")?;
+            for (name, value) in params {
+                let value = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace(']', "\\]");
+                write!(message, " {name}=\"{value}\"")?;
+            }
+            message.push(']');
+        }
+        write!(message, " {msg}")?;
+        self.socket
+            .send(message.as_bytes())
+            .context("send to syslog")?;
+        Ok(())
+    }
+}
+
+impl Sink for Syslog {
+    /// one informational message per digest entry
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        for section in &digest.sections {
+            for entry in section.entries() {
+                let mut params: Vec<(&str, String)> = Vec::new();
+                if let Some(title) = &section.title {
+                    params.push(("section", title.clone()));
+                }
+                params.extend(entry.labels.iter().map(|&(c, v)| (c, v.to_string())));
+                params.push(("count", entry.count.to_string()));
+                let msg = params
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                self.send(6, digest.end, "digest", &params, &msg)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn alert(&mut self, alert: &Alert) -> Result<()> {
+        let mut params = vec![
+            ("kind", alert.kind.to_string()),
+            ("direction", alert.direction.to_string()),
+        ];
+        if let Some(source) = alert.source {
+            params.push(("src", source.to_string()));
+        }
+        if let Some(port) = alert.port {
+            params.push(("dport", port.to_string()));
+        }
+        params.push(("value", alert.value.to_string()));
+        params.push(("threshold", alert.threshold.to_string()));
+        if let Some(rule) = &alert.rule {
+            params.push(("rule", rule.clone()));
+        }
+        self.send(4, alert.ts, "alert", &params, &alert.to_string())
+    }
+}
+
+/// entries for the systemd journal, using its native protocol
+pub struct Journald {
+    socket: UnixDatagram,
+}
+
+impl Journald {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(path.as_ref())
+            .with_context(|| format!("connect to {}", path.as_ref().display()))?;
+        Ok(Journald { socket })
+    }
+
+    fn send(&self, fields: &[(&str, String)]) -> Result<()> {
+        let mut entry = Vec::new();
+        for (name, value) in [("SYSLOG_IDENTIFIER", "pulso".to_string())]
+            .iter()
+            .chain(fields)
+        {
+            if value.contains('\n') {
+                entry.extend_from_slice(name.as_bytes());
+                entry.push(b'\n');
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+                entry.extend_from_slice(value.as_bytes());
+            } else {
+                write!(entry, "{name}={value}")?;
+            }
+            entry.push(b'\n');
+        }
+        self.socket.send(&entry).context("send to journald")?;
+        Ok(())
+    }
+}
+
+/// journal field name for a digest column
+fn field_name(column: &str) -> String {
+    match column {
+        "src" => "SOURCE".to_string(),
+        "dst" => "DESTINATION".to_string(),
+        "dport" => "PORT".to_string(),
+        other => other.to_uppercase().replace('-', "_"),
+    }
+}
+
+impl Sink for Journald {
+    /// one informational entry per digest entry, with a field per label and a COUNT field
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        for section in &digest.sections {
+            for entry in section.entries() {
+                let mut fields = vec![("PRIORITY", "6".to_string())];
+                let mut message = Vec::new();
+                if let Some(title) = &section.title {
+                    fields.push(("SECTION", title.clone()));
+                    message.push(format!("section={title}"));
+                }
+                let names: Vec<String> = entry.labels.iter().map(|(c, _)| field_name(c)).collect();
+                for ((column, value), name) in entry.labels.iter().zip(&names) {
+                    fields.push((name, value.to_string()));
+                    message.push(format!("{column}={value}"));
+                }
+                fields.push(("COUNT", entry.count.to_string()));
+                message.push(format!("count={}", entry.count));
+                fields.push(("MESSAGE", message.join(" ")));
+                self.send(&fields)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn alert(&mut self, alert: &Alert) -> Result<()> {
+        let mut fields = vec![
+            ("PRIORITY", "4".to_string()),
+            ("MESSAGE", format!("alert: {alert}")),
+            ("ALERT_KIND", alert.kind.to_string()),
+            ("DIRECTION", alert.direction.to_string()),
+            ("VALUE", alert.value.to_string()),
+            ("THRESHOLD", alert.threshold.to_string()),
+        ];
+        if let Some(source) = alert.source {
+            fields.push(("SOURCE", source.to_string()));
+        }
+        if let Some(port) = alert.port {
+            fields.push(("PORT", port.to_string()));
+        }
+        if let Some(rule) = &alert.rule {
+            fields.push(("RULE", rule.clone()));
+        }
+        self.send(&fields)
+    }
+}
+
+/// destinations for digests and alerts
+#[derive(Default)]
+pub struct Outputs {
+    alerts: AlertOutputs,
+    sinks: Vec<Box<dyn Sink>>,
+    blocklist: Option<Blocklist>,
+}
+
+impl Outputs {
+    /// where alerts go besides the sinks
+    pub fn alerts(mut self, alerts: AlertOutputs) -> Self {
+        self.alerts = alerts;
+        self
+    }
+
+    /// also send digests and alerts to a sink
+    pub fn sink(mut self, sink: Box<dyn Sink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// also rewrite a blocklist at the end of each window
+    pub fn blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    /// sends the alert to the alert outputs and to every sink, even if some of them fail
+    pub fn emit(&mut self, alert: &Alert) -> Result<()> {
+        let mut result = self.alerts.emit(alert);
+        if let Err(e) = &result {
+            warn!("alert output error: {e:#}");
+        }
+        for sink in self.sinks.iter_mut() {
+            if let Err(e) = sink.alert(alert) {
+                warn!("alert output error: {e:#}");
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// closes the current window of the collector, and sends its digest.
+    /// the blocklist is rewritten afterwards, and a failure to write it does not hold back the digest
+    pub fn flush(&mut self, collector: &mut Collector) -> Result<()> {
+        if let Some(blocklist) = &mut self.blocklist {
+            blocklist.add(collector);
+        }
+        let digest = collector.take_digest(unix_now())?;
+        let sent = self.digest(&digest);
+        if let Some(blocklist) = &self.blocklist {
+            match blocklist.write() {
+                Ok(listed) => info!("{listed} sources blocklisted"),
+                Err(e) => {
+                    warn!("blocklist error: {e:#}");
+                    return sent.and(Err(e));
+                }
+            }
+        }
+        sent
+    }
+
+    /// sends the digest to every sink, even if some of them fail
+    pub fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let mut result = Ok(());
+        for sink in self.sinks.iter_mut() {
+            if let Err(e) = sink.digest(digest) {
+                warn!("digest output error: {e:#}");
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// closes every sink, even if some of them fail
+    pub fn close(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for sink in self.sinks.iter_mut() {
+            if let Err(e) = sink.close() {
+                warn!("output close error: {e:#}");
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// waits for pending alert actions to run
+    pub fn finish(&mut self) {
+        self.alerts.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::os::unix::net::UnixDatagram;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::aggregate::{Digest, DigestFormat, Row, Section, Table};
+    use crate::alert::{Alert, AlertKind};
+    use crate::capture::Direction;
+    use crate::output::{Journald, Outputs, Sink, Syslog, Target, Text};
+
+    fn socket(name: &str) -> (UnixDatagram, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("pulso-{name}-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        (UnixDatagram::bind(&path).unwrap(), path)
+    }
+
+    fn digest() -> Digest {
+        let table = Table {
+            rows: vec![Row {
+                group: "22".into(),
+                total: 3,
+                items: vec![("10.0.0.1".into(), 3)],
+            }],
+        };
+        Digest {
+            end: Duration::from_secs(1697723025),
+            sections: vec![Section::new(
+                Some("inbound".into()),
+                &["dport", "src"],
+                table,
+            )],
+            ..Default::default()
+        }
+    }
+
+    fn received(server: &UnixDatagram) -> String {
+        let mut buf = [0u8; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    /// counts the alerts it receives, failing for every one if asked to
+    struct Counting(Rc<Cell<usize>>, bool);
+
+    impl Sink for Counting {
+        fn digest(&mut self, _digest: &Digest) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn alert(&mut self, _alert: &Alert) -> anyhow::Result<()> {
+            self.0.set(self.0.get() + 1);
+            if self.1 {
+                anyhow::bail!("failed");
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_alert_reaches_every_sink() {
+        let received = Rc::new(Cell::new(0));
+        let mut outputs = Outputs::default()
+            .sink(Box::new(Counting(received.clone(), true)))
+            .sink(Box::new(Counting(received.clone(), false)));
+        let alert = Alert {
+            ts: Duration::from_secs(1),
+            kind: AlertKind::VerticalScan,
+            direction: Direction::In,
+            source: None,
+            port: None,
+            value: 11,
+            threshold: 10,
+            window: Duration::from_secs(60),
+            rule: None,
+            rule_index: None,
+        };
+        assert!(outputs.emit(&alert).is_err());
+        assert_eq!(received.get(), 2);
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!("stdout".parse::<Target>().unwrap(), Target::Stdout);
+        assert_eq!(
+            "file:/tmp/digest".parse::<Target>().unwrap(),
+            Target::File("/tmp/digest".into())
+        );
+        assert_eq!(
+            "dogstatsd:127.0.0.1:8125".parse::<Target>().unwrap(),
+            Target::DogStatsd("127.0.0.1:8125".into())
+        );
+        assert_eq!(
+            "influx:http://localhost:8086/write?db=pulso"
+                .parse::<Target>()
+                .unwrap(),
+            Target::InfluxHttp("http://localhost:8086/write?db=pulso".parse().unwrap())
+        );
+        assert!(matches!(
+            "otlp:http://127.0.0.1:4318/v1/metrics".parse::<Target>(),
+            Ok(Target::Otlp(_))
+        ));
+        assert!("otlp:https://collector".parse::<Target>().is_err());
+        assert!("file:".parse::<Target>().is_err());
+        assert!("kafka".parse::<Target>().is_err());
+
+        assert!(Target::Ipfix("127.0.0.1:4739".into()).needs_flows());
+        assert!(!Target::Stdout.needs_flows());
+    }
+
+    #[test]
+    fn test_csv_header_once() {
+        let mut out = Vec::new();
+        let mut text = Text::new(&mut out).format(DigestFormat::Csv, true);
+        text.digest(&Digest::default()).unwrap();
+        text.digest(&Digest::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "window_start,window_end,source,port,count\n"
+        );
+    }
+
+    #[test]
+    fn test_syslog_digest() {
+        let (server, path) = socket("syslog");
+        let mut syslog = Syslog::connect(&path).unwrap();
+        syslog.digest(&digest()).unwrap();
+
+        let message = received(&server);
+        assert!(
+            message.starts_with("<30>1 2023-10-19T13:43:45.000000Z "),
+            "{message}"
+        );
+        assert!(message.ends_with(
+            " digest [pulso@32473 section=\"inbound\" dport=\"22\" src=\"10.0.0.1\" count=\"3\"] \
+             section=inbound dport=22 src=10.0.0.1 count=3"
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_syslog_alert_rule() {
+        let (server, path) = socket("syslog-alert");
+        let mut syslog = Syslog::connect(&path).unwrap();
+        let alert = Alert {
+            ts: Duration::from_secs(1697723025),
+            kind: AlertKind::SourceThreshold,
+            direction: Direction::In,
+            source: None,
+            port: Some(22),
+            value: 3,
+            threshold: 2,
+            window: Duration::from_secs(10),
+            rule: Some("port=22 sources>2 per 10s".into()),
+            rule_index: Some(0),
+        };
+        syslog.alert(&alert).unwrap();
+
+        let message = received(&server);
+        assert!(
+            message.contains(
+                " alert [pulso@32473 kind=\"source-threshold\" direction=\"inbound\" dport=\"22\" \
+                 value=\"3\" threshold=\"2\" rule=\"port=22 sources>2 per 10s\"] "
+            ),
+            "{message}"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_journald_digest() {
+        let (server, path) = socket("journald");
+        let mut journald = Journald::connect(&path).unwrap();
+        journald.digest(&digest()).unwrap();
+
+        assert_eq!(
+            received(&server),
+            "SYSLOG_IDENTIFIER=pulso\n\
+             PRIORITY=6\n\
+             SECTION=inbound\n\
+             PORT=22\n\
+             SOURCE=10.0.0.1\n\
+             COUNT=3\n\
+             MESSAGE=section=inbound dport=22 src=10.0.0.1 count=3\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use anyhow::{Context, Result};
+use arrow_array::{
+    ArrayRef, RecordBatch, StringArray, TimestampMillisecondArray, UInt16Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use log::info;
+
+use crate::aggregate::Digest;
+use crate::output::{OutputOptions, Sink};
+
+fn timestamp(name: &str) -> Field {
+    Field::new(
+        name,
+        DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        false,
+    )
+}
+
+/// one row per connection key counted in a window
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        timestamp("window_start"),
+        timestamp("window_end"),
+        Field::new("interface", DataType::Utf8, false),
+        Field::new("protocol", DataType::Utf8, false),
+        Field::new("direction", DataType::Utf8, false),
+        Field::new("source", DataType::Utf8, true),
+        Field::new("destination", DataType::Utf8, true),
+        Field::new("port", DataType::UInt16, true),
+        Field::new("count", DataType::UInt64, false),
+        timestamp("first_seen"),
+        timestamp("last_seen"),
+    ]))
+}
+
+/// the connection records of a window
+pub fn batch(digest: &Digest, device: &str) -> Result<RecordBatch> {
+    let flows = &digest.flows;
+    let ms = |ts: Duration| ts.as_millis() as i64;
+    let times = |values: Vec<i64>| -> ArrayRef {
+        Arc::new(TimestampMillisecondArray::from(values).with_timezone("UTC"))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        times(vec![ms(digest.start); flows.len()]),
+        times(vec![ms(digest.end); flows.len()]),
+        Arc::new(StringArray::from(vec![device; flows.len()])),
+        Arc::new(StringArray::from(vec!["tcp"; flows.len()])),
+        Arc::new(StringArray::from_iter_values(
+            flows.iter().map(|f| f.key.direction.to_string()),
+        )),
+        Arc::new(StringArray::from_iter(
+            flows
+                .iter()
+                .map(|f| f.key.source_ip.map(|ip| ip.to_string())),
+        )),
+        Arc::new(StringArray::from_iter(
+            flows.iter().map(|f| f.key.dest_ip.map(|ip| ip.to_string())),
+        )),
+        Arc::new(UInt16Array::from_iter(
+            flows.iter().map(|f| f.key.dest_port),
+        )),
+        Arc::new(UInt64Array::from_iter_values(flows.iter().map(|f| f.count))),
+        times(flows.iter().map(|f| ms(f.first)).collect()),
+        times(flows.iter().map(|f| ms(f.last)).collect()),
+    ];
+    Ok(RecordBatch::try_new(schema(), columns)?)
+}
+
+struct Current {
+    start: Duration,
+    path: PathBuf,
+    writer: ArrowWriter<File>,
+}
+
+/// connection records in parquet files named `<device>-<unix time>.parquet`, one per rotation period.
+/// a file is written as `.parquet.tmp` and renamed once complete, so readers only see whole files
+pub struct Parquet {
+    dir: PathBuf,
+    device: String,
+    rotate: Duration,
+    properties: WriterProperties,
+    current: Option<Current>,
+}
+
+impl Parquet {
+    pub fn open(dir: &Path, options: &OutputOptions) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(options.row_group)
+            .set_compression(Compression::SNAPPY)
+            .build();
+        Ok(Parquet {
+            dir: dir.to_path_buf(),
+            device: options.device.clone(),
+            rotate: options.rotate,
+            properties,
+            current: None,
+        })
+    }
+
+    /// the writer of the file which a window starting at this time belongs in
+    fn writer(&mut self, start: Duration) -> Result<&mut ArrowWriter<File>> {
+        if matches!(&self.current, Some(current) if start >= current.start + self.rotate) {
+            self.close()?;
+        }
+        if self.current.is_none() {
+            let name = format!("{}-{}.parquet", self.device, start.as_secs());
+            let path = self.dir.join(name);
+            let partial = path.with_extension("parquet.tmp");
+            let file =
+                File::create(&partial).with_context(|| format!("create {}", partial.display()))?;
+            let writer = ArrowWriter::try_new(file, schema(), Some(self.properties.clone()))?;
+            self.current = Some(Current {
+                start,
+                path,
+                writer,
+            });
+        }
+        Ok(&mut self.current.as_mut().unwrap().writer)
+    }
+}
+
+impl Sink for Parquet {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        if digest.flows.is_empty() {
+            return Ok(());
+        }
+        let batch = batch(digest, &self.device)?;
+        self.writer(digest.start)?
+            .write(&batch)
+            .context("write parquet rows")?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(current) = self.current.take() {
+            current.writer.close().context("close parquet file")?;
+            let partial = current.path.with_extension("parquet.tmp");
+            fs::rename(&partial, &current.path)
+                .with_context(|| format!("rename {}", partial.display()))?;
+            info!("wrote {}", current.path.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::time::Duration;
+
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use arrow_array::{StringArray, UInt16Array, UInt64Array};
+
+    use crate::aggregate::Digest;
+    use crate::capture::Direction;
+    use crate::collector::{ConnectionKey, Flow};
+    use crate::output::{OutputOptions, Sink};
+    use crate::parquet::Parquet;
+    use crate::sensitive::IpAddress;
+
+    fn window(start: u64, ports: &[u16]) -> Digest {
+        let flows = ports
+            .iter()
+            .map(|&port| Flow {
+                key: ConnectionKey {
+                    direction: Direction::In,
+                    source_ip: Some(IpAddress::V4([192, 0, 2, 1])),
+                    dest_ip: None,
+                    dest_port: Some(port),
+                },
+                count: port as u64,
+                first: Duration::from_secs(start),
+                last: Duration::from_secs(start + 1),
+            })
+            .collect();
+        Digest {
+            start: Duration::from_secs(start),
+            end: Duration::from_secs(start + 60),
+            flows,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rotating_files() {
+        let dir = std::env::temp_dir().join(format!("pulso-parquet-{}", std::process::id()));
+        let options = OutputOptions {
+            device: "eth0".into(),
+            row_group: 2,
+            rotate: Duration::from_secs(120),
+            ..Default::default()
+        };
+        let mut parquet = Parquet::open(&dir, &options).unwrap();
+        parquet.digest(&window(1697723000, &[22, 80, 443])).unwrap();
+        parquet.digest(&window(1697723060, &[5432])).unwrap();
+        assert!(dir.join("eth0-1697723000.parquet.tmp").exists());
+
+        parquet.digest(&window(1697723120, &[25])).unwrap();
+        parquet.close().unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["eth0-1697723000.parquet", "eth0-1697723120.parquet"]
+        );
+
+        let file = File::open(dir.join("eth0-1697723000.parquet")).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        let row_groups: Vec<i64> = builder
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|g| g.num_rows())
+            .collect();
+        assert_eq!(row_groups, [2, 2]);
+
+        let batches: Vec<_> = builder.build().unwrap().map(|b| b.unwrap()).collect();
+        let column = |name: &str| {
+            batches
+                .iter()
+                .map(|b| b.column_by_name(name).unwrap().clone())
+                .collect::<Vec<_>>()
+        };
+        let ports: Vec<Option<u16>> = column("port")
+            .iter()
+            .flat_map(|a| a.as_any().downcast_ref::<UInt16Array>().unwrap().iter())
+            .collect();
+        assert_eq!(ports, [Some(22), Some(80), Some(443), Some(5432)]);
+        let counts: u64 = column("count")
+            .iter()
+            .flat_map(|a| a.as_any().downcast_ref::<UInt64Array>().unwrap().iter())
+            .map(Option::unwrap)
+            .sum();
+        assert_eq!(counts, 22 + 80 + 443 + 5432);
+        let interface = column("interface");
+        let interface = interface[0].as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(interface.value(0), "eth0");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::aggregate::{write_csv_rows, CsvKey, Digest, Row, Section, Table, CSV_HEADER};
+use crate::time::parse_rfc3339;
+
+/// connection keys read from the CSV format, summed over the windows of their rows
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CsvDigest {
+    pub start: Duration,
+    pub end: Duration,
+    pub counts: HashMap<CsvKey, u64>,
+}
+
+impl CsvDigest {
+    pub fn merge(&mut self, other: &CsvDigest) {
+        if self.counts.is_empty() {
+            (self.start, self.end) = (other.start, other.end);
+        } else if !other.counts.is_empty() {
+            self.start = self.start.min(other.start);
+            self.end = self.end.max(other.end);
+        }
+        for (key, count) in &other.counts {
+            *self.counts.entry(key.clone()).or_default() += count;
+        }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        writeln!(out, "{CSV_HEADER}")?;
+        let rows = self.counts.iter().map(|(key, &count)| (key.clone(), count));
+        write_csv_rows(out, self.start, self.end, rows)
+    }
+}
+
+/// a digest read back from one of the formats it is written in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DigestFile {
+    Text(Digest),
+    Csv(CsvDigest),
+}
+
+impl DigestFile {
+    /// reads the CSV format if the input starts with its header, or the text format otherwise
+    pub fn parse(input: &str) -> Result<Self> {
+        match input.lines().next() {
+            Some(CSV_HEADER) => Ok(DigestFile::Csv(parse_csv(input)?)),
+            _ => Ok(DigestFile::Text(parse_text(input)?)),
+        }
+    }
+
+    /// adds the counts of another digest in the same format
+    pub fn merge(&mut self, other: &DigestFile) -> Result<()> {
+        match (self, other) {
+            (DigestFile::Text(digest), DigestFile::Text(other)) => digest.merge(other),
+            (DigestFile::Csv(digest), DigestFile::Csv(other)) => digest.merge(other),
+            _ => bail!("cannot merge text and csv digests"),
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        match self {
+            DigestFile::Text(digest) => digest.write(out),
+            DigestFile::Csv(digest) => digest.write(out),
+        }
+    }
+}
+
+fn labeled_count(token: &str) -> Result<(String, u64)> {
+    let (label, count) = token
+        .rsplit_once(':')
+        .ok_or(anyhow!("expected <label>:<count>, found {token}"))?;
+    let count = count
+        .parse()
+        .with_context(|| format!("invalid count in {token}"))?;
+    Ok((label.to_string(), count))
+}
+
+fn parse_row(line: &str) -> Result<Row> {
+    let mut tokens = line.split_whitespace();
+    let (group, total) = labeled_count(tokens.next().unwrap_or_default())?;
+    let items = tokens.map(labeled_count).collect::<Result<_>>()?;
+    Ok(Row {
+        group,
+        total,
+        items,
+    })
+}
+
+/// reads the text format. the windows of a periodic digest are summed into one, spanning them all.
+/// column names are not part of the format, so the sections have none
+pub fn parse_text(input: &str) -> Result<Digest> {
+    let mut digest = Digest::default();
+    let mut window = Digest::default();
+    for (n, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let context = || format!("line {}", n + 1);
+        if let Some(header) = line.strip_prefix("# ") {
+            match header.strip_prefix("window ") {
+                Some(span) => {
+                    let (start, end) = span
+                        .split_once(' ')
+                        .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)))
+                        .ok_or(anyhow!("expected # window <start> <end>"))
+                        .with_context(context)?;
+                    digest.merge(&window);
+                    window = Digest {
+                        start: Duration::from_secs(start),
+                        end: Duration::from_secs(end),
+                        periodic: true,
+                        ..Default::default()
+                    };
+                }
+                None => window.sections.push(Section::new(
+                    Some(header.to_string()),
+                    &[],
+                    Table::default(),
+                )),
+            }
+            continue;
+        }
+        let row = parse_row(line).with_context(context)?;
+        if window.sections.is_empty() {
+            window
+                .sections
+                .push(Section::new(None, &[], Table::default()));
+        }
+        window.sections.last_mut().unwrap().table.rows.push(row);
+    }
+    digest.merge(&window);
+    Ok(digest)
+}
+
+/// splits a CSV line into fields, unquoting them
+fn csv_fields(line: &str) -> Result<Vec<String>> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match (c, quoted) {
+            ('"', false) if field.is_empty() => quoted = true,
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            (',', false) => fields.push(String::new()),
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        bail!("unterminated quote");
+    }
+    Ok(fields)
+}
+
+/// reads the CSV format, summing the rows of each source and port
+pub fn parse_csv(input: &str) -> Result<CsvDigest> {
+    let mut digest = CsvDigest::default();
+    for (n, line) in input.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row = || -> Result<CsvDigest> {
+            let fields = csv_fields(line)?;
+            let [start, end, source, port, count] = <[String; 5]>::try_from(fields)
+                .map_err(|f| anyhow!("expected 5 fields, found {}", f.len()))?;
+            let port = match port.as_str() {
+                "" => None,
+                port => Some(port.parse().context("invalid port")?),
+            };
+            let count = count.parse().context("invalid count")?;
+            Ok(CsvDigest {
+                start: parse_rfc3339(&start)?,
+                end: parse_rfc3339(&end)?,
+                counts: HashMap::from([((source, port), count)]),
+            })
+        };
+        digest.merge(&row().with_context(|| format!("line {}", n + 1))?);
+    }
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::parse::{csv_fields, parse_text, DigestFile};
+
+    fn merged(inputs: &[&str]) -> String {
+        let mut digests = inputs.iter().map(|i| DigestFile::parse(i).unwrap());
+        let mut merged = digests.next().unwrap();
+        for digest in digests {
+            merged.merge(&digest).unwrap();
+        }
+        let mut out = Vec::new();
+        merged.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let digest = parse_text(
+            "# window 1697723000 1697723060\n\
+             # inbound\n\
+             22:5 aa:3 bb:2\n\
+             # window 1697723060 1697723120\n\
+             # inbound\n\
+             22:1 aa:1\n\
+             # fingerprints\n\
+             22:1 64240_2-4-8-1-3_1460_7:1\n",
+        )
+        .unwrap();
+        assert!(digest.periodic);
+        assert_eq!(digest.start, Duration::from_secs(1697723000));
+        assert_eq!(digest.end, Duration::from_secs(1697723120));
+        assert_eq!(digest.sections.len(), 2);
+        assert_eq!(digest.sections[0].title.as_deref(), Some("inbound"));
+        let row = &digest.sections[0].table.rows[0];
+        assert_eq!((row.group.as_str(), row.total), ("22", 6));
+        assert_eq!(row.items, [("aa".to_string(), 4), ("bb".to_string(), 2)]);
+
+        let error = parse_text("22:5\n80:x\n").unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "line 2: invalid count in 80:x: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn test_merge_text() {
+        assert_eq!(
+            merged(&["22:5 aa:3 bb:2\n80:1 aa:1\n", "80:9 cc:9\n22:1 bb:1\n"]),
+            "80:10 cc:9 aa:1\n22:6 aa:3 bb:3\n"
+        );
+        assert_eq!(
+            merged(&[
+                "# inbound\n22:2\n# outbound\n443:1\n",
+                "# outbound\n443:2\n80:2\n"
+            ]),
+            "# inbound\n22:2\n# outbound\n443:3\n80:2\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_csv() {
+        let header = "window_start,window_end,source,port,count\n";
+        let a = format!(
+            "{header}2023-10-19T13:00:00.000000Z,2023-10-19T14:00:00.000000Z,aa,22,3\n\
+             2023-10-19T13:00:00.000000Z,2023-10-19T14:00:00.000000Z,bb,22,1\n"
+        );
+        let b =
+            format!("{header}2023-10-19T14:00:00.000000Z,2023-10-19T15:00:00.000000Z,bb,22,4\n");
+        assert_eq!(
+            merged(&[&a, &b]),
+            format!(
+                "{header}2023-10-19T13:00:00.000000Z,2023-10-19T15:00:00.000000Z,bb,22,5\n\
+                 2023-10-19T13:00:00.000000Z,2023-10-19T15:00:00.000000Z,aa,22,3\n"
+            )
+        );
+        let error = DigestFile::parse(&format!("{header}x,y,aa,22,1\n")).unwrap_err();
+        assert!(format!("{error:#}").contains("line 2"));
+
+        let mut text = DigestFile::parse("22:1\n").unwrap();
+        assert!(text.merge(&DigestFile::parse(&a).unwrap()).is_err());
+
+        assert_eq!(
+            csv_fields(r#"a,"b,""c""",,d"#).unwrap(),
+            ["a", r#"b,"c""#, "", "d"]
+        );
+        assert!(csv_fields(r#"a,"b"#).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::alert::{Alert, AlertKind, Detector};
+use crate::capture::{Direction, ExtractedHeaders};
+use crate::sensitive::IpAddress;
+
+/// distinct values seen within a sliding window, with the time each was last seen
+#[derive(Debug)]
+struct Recent<T> {
+    last_seen: HashMap<T, Duration>,
+}
+
+impl<T> Default for Recent<T> {
+    fn default() -> Self {
+        Recent {
+            last_seen: HashMap::new(),
+        }
+    }
+}
+
+impl<T: std::hash::Hash + Eq> Recent<T> {
+    /// returns the number of distinct values before and after the observation
+    fn observe(&mut self, value: T, now: Duration, window: Duration) -> (usize, usize) {
+        self.expire(now, window);
+        let before = self.last_seen.len();
+        self.last_seen.insert(value, now);
+        (before, self.last_seen.len())
+    }
+
+    fn expire(&mut self, now: Duration, window: Duration) {
+        self.last_seen
+            .retain(|_, &mut seen| now.saturating_sub(seen) < window);
+    }
+}
+
+/// flags sources which connect to more than a threshold of distinct ports (vertical scan)
+/// or distinct hosts on one port (horizontal scan) within a sliding window.
+/// an alert is raised each time a count crosses its threshold.
+pub struct PortScanDetector {
+    window: Duration,
+    max_ports: Option<u64>,
+    max_hosts: Option<u64>,
+    ports: HashMap<(Direction, IpAddress), Recent<u16>>,
+    hosts: HashMap<(Direction, IpAddress, u16), Recent<IpAddress>>,
+    next_sweep: Duration,
+}
+
+impl PortScanDetector {
+    pub fn new(window: Duration, max_ports: Option<u64>, max_hosts: Option<u64>) -> Self {
+        PortScanDetector {
+            window,
+            max_ports,
+            max_hosts,
+            ports: HashMap::new(),
+            hosts: HashMap::new(),
+            next_sweep: Duration::ZERO,
+        }
+    }
+
+    /// forget sources which have been quiet for a whole window
+    fn sweep(&mut self, now: Duration) {
+        let window = self.window;
+        self.ports.retain(|_, recent| {
+            recent.expire(now, window);
+            !recent.last_seen.is_empty()
+        });
+        self.hosts.retain(|_, recent| {
+            recent.expire(now, window);
+            !recent.last_seen.is_empty()
+        });
+        self.next_sweep = now + window;
+    }
+
+    /// thresholds are crossed one distinct value at a time, so the observed value is always one more
+    fn alert(
+        &self,
+        kind: AlertKind,
+        direction: Direction,
+        headers: &ExtractedHeaders,
+        threshold: u64,
+    ) -> Alert {
+        Alert {
+            ts: headers.timestamp(),
+            kind,
+            direction,
+            source: Some(headers.source_ip),
+            port: Some(headers.dest_port).filter(|_| kind == AlertKind::HorizontalScan),
+            value: threshold + 1,
+            threshold,
+            window: self.window,
+            rule: None,
+            rule_index: None,
+        }
+    }
+}
+
+impl Detector for PortScanDetector {
+    fn observe(
+        &mut self,
+        direction: Direction,
+        headers: &ExtractedHeaders,
+        alerts: &mut Vec<Alert>,
+    ) {
+        let now = headers.timestamp();
+        if now >= self.next_sweep {
+            self.sweep(now);
+        }
+
+        if let Some(max_ports) = self.max_ports {
+            let (before, after) = self
+                .ports
+                .entry((direction, headers.source_ip))
+                .or_default()
+                .observe(headers.dest_port, now, self.window);
+            if before as u64 <= max_ports && after as u64 > max_ports {
+                alerts.push(self.alert(AlertKind::VerticalScan, direction, headers, max_ports));
+            }
+        }
+
+        if let Some(max_hosts) = self.max_hosts {
+            let (before, after) = self
+                .hosts
+                .entry((direction, headers.source_ip, headers.dest_port))
+                .or_default()
+                .observe(headers.dest_ip, now, self.window);
+            if before as u64 <= max_hosts && after as u64 > max_hosts {
+                alerts.push(self.alert(AlertKind::HorizontalScan, direction, headers, max_hosts));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use libc::timeval;
+
+    use crate::alert::{AlertKind, Detector};
+    use crate::capture::{Direction, ExtractedHeaders};
+    use crate::portscan::PortScanDetector;
+    use crate::sensitive::IpAddress;
+
+    const SOURCE: IpAddress = IpAddress::V4([192, 0, 2, 1]);
+
+    fn headers(secs: i64, dest_ip: IpAddress, dest_port: u16) -> ExtractedHeaders {
+        ExtractedHeaders {
+            source_ip: SOURCE,
+            source_port: 40000,
+            dest_ip,
+            dest_port,
+            capture_ts: timeval {
+                tv_sec: secs,
+                tv_usec: 0,
+            },
+            syn: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_vertical_scan() {
+        let mut detector = PortScanDetector::new(Duration::from_secs(10), Some(2), None);
+        let dest = IpAddress::V4([10, 0, 0, 1]);
+        let mut alerts = Vec::new();
+
+        detector.observe(Direction::In, &headers(0, dest, 22), &mut alerts);
+        detector.observe(Direction::In, &headers(1, dest, 23), &mut alerts);
+        detector.observe(Direction::In, &headers(2, dest, 23), &mut alerts);
+        assert!(alerts.is_empty());
+
+        detector.observe(Direction::In, &headers(3, dest, 24), &mut alerts);
+        detector.observe(Direction::In, &headers(4, dest, 25), &mut alerts);
+        assert_eq!(alerts.len(), 1, "alert once per crossing");
+        assert_eq!(alerts[0].kind, AlertKind::VerticalScan);
+        assert_eq!(alerts[0].source, Some(SOURCE));
+        assert_eq!(alerts[0].ts, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_vertical_scan_window_expiry() {
+        let mut detector = PortScanDetector::new(Duration::from_secs(10), Some(2), None);
+        let dest = IpAddress::V4([10, 0, 0, 1]);
+        let mut alerts = Vec::new();
+
+        for (secs, port) in [(0, 22), (11, 23), (22, 24), (33, 25)] {
+            detector.observe(Direction::In, &headers(secs, dest, port), &mut alerts);
+        }
+        assert!(alerts.is_empty());
+    }
+
+    #[test]
+    fn test_horizontal_scan() {
+        let mut detector = PortScanDetector::new(Duration::from_secs(10), None, Some(1));
+        let mut alerts = Vec::new();
+
+        detector.observe(
+            Direction::In,
+            &headers(0, IpAddress::V4([10, 0, 0, 1]), 22),
+            &mut alerts,
+        );
+        detector.observe(
+            Direction::In,
+            &headers(0, IpAddress::V4([10, 0, 0, 2]), 80),
+            &mut alerts,
+        );
+        assert!(alerts.is_empty());
+
+        detector.observe(
+            Direction::In,
+            &headers(1, IpAddress::V4([10, 0, 0, 2]), 22),
+            &mut alerts,
+        );
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::HorizontalScan);
+        assert_eq!(alerts[0].port, Some(22));
+        assert_eq!(alerts[0].value, 2);
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+
+use crate::aggregate::Digest;
+use crate::capture::Direction;
+use crate::output::Sink;
+
+const MAGIC: &[u8; 8] = b"PULSORRD";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 8 + 4 + 4 + Resolution::ALL.len() as u64 * 8;
+/// bucket start, sum and max, each a little endian u64
+const ROW_LEN: u64 = 24;
+
+/// row sizes of an archive, from finest to coarsest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    pub const ALL: [Resolution; 3] = [Resolution::Minute, Resolution::Hour, Resolution::Day];
+
+    /// seconds per row
+    pub fn step(self) -> u64 {
+        match self {
+            Resolution::Minute => 60,
+            Resolution::Hour => 3600,
+            Resolution::Day => 86400,
+        }
+    }
+
+    /// rows kept: a day of minutes, 30 days of hours and 5 years of days
+    pub fn rows(self) -> u64 {
+        match self {
+            Resolution::Minute => 1440,
+            Resolution::Hour => 720,
+            Resolution::Day => 1826,
+        }
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "minute" => Ok(Resolution::Minute),
+            "hour" => Ok(Resolution::Hour),
+            "day" => Ok(Resolution::Day),
+            other => Err(anyhow!(
+                "unknown resolution: {other} (expected minute, hour or day)"
+            )),
+        }
+    }
+}
+
+/// how the minutes within a row are combined when it is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consolidation {
+    /// connections in the row
+    Sum,
+    /// connections in the busiest minute of the row
+    Max,
+    /// connections per minute
+    Average,
+}
+
+impl FromStr for Consolidation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sum" => Ok(Consolidation::Sum),
+            "max" => Ok(Consolidation::Max),
+            "average" => Ok(Consolidation::Average),
+            other => Err(anyhow!(
+                "unknown consolidation: {other} (expected sum, max or average)"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Row {
+    bucket: u64,
+    sum: u64,
+    max: u64,
+}
+
+fn header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.extend_from_slice(&(Resolution::ALL.len() as u32).to_le_bytes());
+    for resolution in Resolution::ALL {
+        header.extend_from_slice(&(resolution.step() as u32).to_le_bytes());
+        header.extend_from_slice(&(resolution.rows() as u32).to_le_bytes());
+    }
+    header
+}
+
+/// a fixed size file of counters for one direction and port, with an archive per resolution.
+/// each row holds the start of its bucket, so rows left over from a previous lap read as zero
+pub struct RoundRobin {
+    file: File,
+}
+
+impl RoundRobin {
+    /// opens an archive, creating it if it doesn't exist
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("open archive {}", path.display()))?;
+        if file.metadata()?.len() == 0 {
+            file.write_all_at(&header(), 0)?;
+            file.set_len(Self::size())?;
+        }
+        Self::check(file, path)
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("open archive {}", path.display()))?;
+        Self::check(file, path)
+    }
+
+    fn check(file: File, path: &Path) -> Result<Self> {
+        let expected = header();
+        let mut found = vec![0; expected.len()];
+        file.read_exact_at(&mut found, 0)
+            .with_context(|| format!("read archive {}", path.display()))?;
+        if found != expected || file.metadata()?.len() != Self::size() {
+            bail!("{} is not a pulso archive", path.display());
+        }
+        Ok(RoundRobin { file })
+    }
+
+    fn size() -> u64 {
+        HEADER_LEN
+            + Resolution::ALL
+                .iter()
+                .map(|r| r.rows() * ROW_LEN)
+                .sum::<u64>()
+    }
+
+    fn offset(resolution: Resolution, bucket: u64) -> u64 {
+        let preceding: u64 = Resolution::ALL
+            .iter()
+            .take_while(|&&r| r != resolution)
+            .map(|r| r.rows() * ROW_LEN)
+            .sum();
+        let index = bucket / resolution.step() % resolution.rows();
+        HEADER_LEN + preceding + index * ROW_LEN
+    }
+
+    fn read(&self, resolution: Resolution, bucket: u64) -> Result<Row> {
+        let mut buf = [0u8; ROW_LEN as usize];
+        self.file
+            .read_exact_at(&mut buf, Self::offset(resolution, bucket))?;
+        let field = |i: usize| u64::from_le_bytes(buf[i * 8..i * 8 + 8].try_into().unwrap());
+        let row = Row {
+            bucket: field(0),
+            sum: field(1),
+            max: field(2),
+        };
+        Ok(if row.bucket == bucket {
+            row
+        } else {
+            Row {
+                bucket,
+                ..Default::default()
+            }
+        })
+    }
+
+    fn write(&self, resolution: Resolution, row: &Row) -> Result<()> {
+        let mut buf = Vec::with_capacity(ROW_LEN as usize);
+        for field in [row.bucket, row.sum, row.max] {
+            buf.extend_from_slice(&field.to_le_bytes());
+        }
+        self.file
+            .write_all_at(&buf, Self::offset(resolution, row.bucket))?;
+        Ok(())
+    }
+
+    /// adds connections counted at a time to the row containing it at each resolution
+    pub fn update(&self, at: Duration, count: u64) -> Result<()> {
+        let secs = at.as_secs();
+        let mut minute = 0;
+        for resolution in Resolution::ALL {
+            let bucket = secs / resolution.step() * resolution.step();
+            let mut row = self.read(resolution, bucket)?;
+            row.sum += count;
+            if resolution == Resolution::Minute {
+                minute = row.sum;
+            }
+            row.max = row.max.max(minute);
+            self.write(resolution, &row)?;
+        }
+        Ok(())
+    }
+
+    /// consolidated values of the rows between two unix times, as (bucket start, value).
+    /// rows which have already been overwritten are left out
+    pub fn fetch(
+        &self,
+        resolution: Resolution,
+        consolidation: Consolidation,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<(u64, f64)>> {
+        let step = resolution.step();
+        let last = end / step * step;
+        let first = (start / step * step).max(last.saturating_sub((resolution.rows() - 1) * step));
+        (first..=last)
+            .step_by(step as usize)
+            .map(|bucket| {
+                let row = self.read(resolution, bucket)?;
+                let value = match consolidation {
+                    Consolidation::Sum => row.sum as f64,
+                    Consolidation::Max => row.max as f64,
+                    Consolidation::Average => {
+                        (row.sum as f64 * 60.0 / step as f64 * 100.0).round() / 100.0
+                    }
+                };
+                Ok((bucket, value))
+            })
+            .collect()
+    }
+}
+
+/// per-port counters in round-robin archives, one file per direction and port in a directory.
+/// only the listed ports are archived, as each archive has a fixed size.
+/// a window is counted in the rows containing its midpoint, so windows should not exceed a minute
+pub struct Rrd {
+    dir: PathBuf,
+    ports: BTreeSet<u16>,
+}
+
+impl Rrd {
+    pub fn open(dir: &Path, ports: &[u16]) -> Result<Self> {
+        if ports.is_empty() {
+            bail!("no ports to archive (--rrd-ports)");
+        }
+        fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        Ok(Rrd {
+            dir: dir.to_path_buf(),
+            ports: ports.iter().copied().collect(),
+        })
+    }
+
+    pub fn path(dir: &Path, direction: Direction, port: u16) -> PathBuf {
+        dir.join(format!("{direction}-{port}.rrd"))
+    }
+}
+
+impl Sink for Rrd {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let midpoint = digest.start + digest.end.saturating_sub(digest.start) / 2;
+        for &(direction, port, count) in &digest.ports {
+            if !self.ports.contains(&port) {
+                continue;
+            }
+            // archives are opened for each update, so that no file is held between windows
+            RoundRobin::create(&Self::path(&self.dir, direction, port))?.update(midpoint, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use crate::aggregate::Digest;
+    use crate::capture::Direction;
+    use crate::output::Sink;
+    use crate::rrd::{Consolidation, Resolution, RoundRobin, Rrd};
+
+    /// 2023-10-19 00:00:00
+    const DAY: u64 = 1697673600;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("pulso-rrd-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_consolidation() {
+        let dir = temp_dir("consolidation");
+        assert!(Rrd::open(&dir, &[]).is_err());
+        let mut rrd = Rrd::open(&dir, &[22]).unwrap();
+        // two 30 second windows in the first minute, one in the second
+        for (start, count) in [(DAY, 3), (DAY + 30, 5), (DAY + 60, 2)] {
+            let digest = Digest {
+                start: Duration::from_secs(start),
+                end: Duration::from_secs(start + 30),
+                ports: vec![(Direction::In, 22, count), (Direction::In, 80, 1)],
+                ..Default::default()
+            };
+            rrd.digest(&digest).unwrap();
+        }
+
+        assert!(
+            !Rrd::path(&dir, Direction::In, 80).exists(),
+            "port not listed"
+        );
+        let path = Rrd::path(&dir, Direction::In, 22);
+        assert_eq!(fs::metadata(&path).unwrap().len(), RoundRobin::size());
+        let archive = RoundRobin::open(&path).unwrap();
+
+        let minutes = archive
+            .fetch(Resolution::Minute, Consolidation::Sum, DAY, DAY + 120)
+            .unwrap();
+        assert_eq!(minutes, vec![(DAY, 8.0), (DAY + 60, 2.0), (DAY + 120, 0.0)]);
+
+        let hour = |consolidation| {
+            archive
+                .fetch(Resolution::Hour, consolidation, DAY, DAY)
+                .unwrap()[0]
+                .1
+        };
+        assert_eq!(hour(Consolidation::Sum), 10.0);
+        assert_eq!(hour(Consolidation::Max), 8.0);
+        assert_eq!(hour(Consolidation::Average), 0.17);
+
+        let days = archive
+            .fetch(Resolution::Day, Consolidation::Sum, DAY, DAY + 86400)
+            .unwrap();
+        assert_eq!(days, vec![(DAY, 10.0), (DAY + 86400, 0.0)]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rows_wrap_around() {
+        let dir = temp_dir("wrap");
+        fs::create_dir_all(&dir).unwrap();
+        let archive = RoundRobin::create(&dir.join("archive.rrd")).unwrap();
+        archive.update(Duration::from_secs(DAY), 4).unwrap();
+        // a day later, the same minute row is reused and the old count is not read
+        archive.update(Duration::from_secs(DAY + 86400), 1).unwrap();
+
+        let minutes = archive
+            .fetch(Resolution::Minute, Consolidation::Sum, 0, DAY + 86400)
+            .unwrap();
+        assert_eq!(minutes.len(), 1440);
+        assert_eq!(minutes.last(), Some(&(DAY + 86400, 1.0)));
+        assert!(minutes.iter().all(|&(bucket, _)| bucket > DAY));
+
+        let hours = archive
+            .fetch(Resolution::Hour, Consolidation::Sum, DAY, DAY + 86400)
+            .unwrap();
+        assert_eq!(hours.first(), Some(&(DAY, 4.0)));
+
+        assert!(RoundRobin::open(&dir.join("missing.rrd")).is_err());
+        fs::write(dir.join("other.rrd"), b"not an archive").unwrap();
+        assert!(RoundRobin::create(&dir.join("other.rrd")).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
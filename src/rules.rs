@@ -0,0 +1,339 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error, Result};
+
+use crate::alert::{Alert, AlertKind, Detector};
+use crate::capture::{Direction, ExtractedHeaders};
+use crate::hooks::Action;
+use crate::sensitive::IpAddress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// new connections
+    Connections,
+    /// distinct source addresses
+    Sources,
+}
+
+/// a threshold on the connections seen within a sliding window, and what to do when it is crossed.
+///
+/// `[port=<port>] [direction=in|out] <connections|sources>><n> per <secs>s [webhook=<url>] [exec=<command>]`
+///
+/// the exec action takes the rest of the line as the command and its arguments, so it must be
+/// the last action.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub port: Option<u16>,
+    pub direction: Option<Direction>,
+    pub metric: Metric,
+    pub threshold: u64,
+    pub window: Duration,
+    pub actions: Vec<Action>,
+}
+
+impl Rule {
+    fn matches(&self, direction: Direction, headers: &ExtractedHeaders) -> bool {
+        self.direction.map_or(true, |d| d == direction)
+            && self.port.map_or(true, |p| p == headers.dest_port)
+    }
+}
+
+/// the condition of the rule, which also identifies it in alerts
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(port) = self.port {
+            write!(f, "port={port} ")?;
+        }
+        match self.direction {
+            Some(Direction::In) => write!(f, "direction=in ")?,
+            Some(Direction::Out) => write!(f, "direction=out ")?,
+            None => (),
+        }
+        let metric = match self.metric {
+            Metric::Connections => "connections",
+            Metric::Sources => "sources",
+        };
+        write!(
+            f,
+            "{metric}>{} per {}s",
+            self.threshold,
+            self.window.as_secs()
+        )
+    }
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut port = None;
+        let mut direction = None;
+        let mut condition = None;
+        let mut window = None;
+        let mut actions = Vec::new();
+
+        let mut rest = s.trim();
+        while !rest.is_empty() {
+            let (token, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            rest = remainder.trim_start();
+
+            if let Some(value) = token.strip_prefix("port=") {
+                port = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid port: {value}"))?,
+                );
+            } else if let Some(value) = token.strip_prefix("direction=") {
+                direction = Some(match value {
+                    "in" => Direction::In,
+                    "out" => Direction::Out,
+                    _ => return Err(anyhow!("invalid direction: {value}")),
+                });
+            } else if token == "per" {
+                let (value, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                rest = remainder.trim_start();
+                let secs = value.strip_suffix('s').unwrap_or(value);
+                window = Some(Duration::from_secs(
+                    secs.parse()
+                        .with_context(|| format!("invalid window: {value}"))?,
+                ));
+            } else if let Some(value) = token.strip_prefix("webhook=") {
+                actions.push(Action::Webhook(value.parse()?));
+            } else if let Some(value) = token.strip_prefix("exec=") {
+                let command: Vec<String> = std::iter::once(value)
+                    .chain(rest.split_whitespace())
+                    .map(String::from)
+                    .collect();
+                if let Some(arg) = command[1..].iter().find(|arg| is_rule_token(arg)) {
+                    return Err(anyhow!(
+                        "exec must be the last action, found {arg} after it"
+                    ));
+                }
+                actions.push(Action::Exec(command));
+                rest = "";
+            } else if let Some((metric, threshold)) = token.split_once('>') {
+                let metric = match metric {
+                    "connections" => Metric::Connections,
+                    "sources" => Metric::Sources,
+                    _ => return Err(anyhow!("unknown metric: {metric}")),
+                };
+                let threshold = threshold
+                    .parse()
+                    .with_context(|| format!("invalid threshold: {threshold}"))?;
+                condition = Some((metric, threshold));
+            } else {
+                return Err(anyhow!("unexpected token: {token}"));
+            }
+        }
+
+        let (metric, threshold) = condition.ok_or(anyhow!("missing threshold"))?;
+        let window = window
+            .filter(|w| !w.is_zero())
+            .ok_or(anyhow!("missing window (per <secs>s)"))?;
+        Ok(Rule {
+            port,
+            direction,
+            metric,
+            threshold,
+            window,
+            actions,
+        })
+    }
+}
+
+/// whether an argument of an exec action looks like part of the rule instead
+fn is_rule_token(arg: &str) -> bool {
+    ["port=", "direction=", "webhook=", "exec="]
+        .iter()
+        .any(|prefix| arg.starts_with(prefix))
+}
+
+/// reads one rule per line, ignoring blank lines and lines starting with #
+pub fn load_rules<P: AsRef<Path>>(path: P) -> Result<Vec<Rule>> {
+    let path = path.as_ref();
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("read rules {}", path.display()))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            line.parse()
+                .with_context(|| format!("{}:{}", path.display(), i + 1))
+        })
+        .collect()
+}
+
+/// connections matching a rule within its window
+#[derive(Default)]
+struct RuleState {
+    events: VecDeque<(Duration, IpAddress)>,
+    sources: HashMap<IpAddress, u64>,
+}
+
+impl RuleState {
+    fn value(&self, metric: Metric) -> u64 {
+        match metric {
+            Metric::Connections => self.events.len() as u64,
+            Metric::Sources => self.sources.len() as u64,
+        }
+    }
+}
+
+/// raises an alert each time the value measured by a rule crosses its threshold
+pub struct RuleDetector {
+    rules: Vec<(Rule, RuleState)>,
+}
+
+impl RuleDetector {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        RuleDetector {
+            rules: rules
+                .into_iter()
+                .map(|rule| (rule, RuleState::default()))
+                .collect(),
+        }
+    }
+}
+
+impl Detector for RuleDetector {
+    fn observe(
+        &mut self,
+        direction: Direction,
+        headers: &ExtractedHeaders,
+        alerts: &mut Vec<Alert>,
+    ) {
+        let now = headers.timestamp();
+        for (index, (rule, state)) in self.rules.iter_mut().enumerate() {
+            if !rule.matches(direction, headers) {
+                continue;
+            }
+
+            while let Some(&(ts, source)) = state.events.front() {
+                if now.saturating_sub(ts) < rule.window {
+                    break;
+                }
+                state.events.pop_front();
+                if let Some(count) = state.sources.get_mut(&source) {
+                    *count -= 1;
+                    if *count == 0 {
+                        state.sources.remove(&source);
+                    }
+                }
+            }
+
+            let before = state.value(rule.metric);
+            state.events.push_back((now, headers.source_ip));
+            *state.sources.entry(headers.source_ip).or_default() += 1;
+            let after = state.value(rule.metric);
+
+            if before <= rule.threshold && after > rule.threshold {
+                alerts.push(Alert {
+                    ts: now,
+                    kind: match rule.metric {
+                        Metric::Connections => AlertKind::ConnectionThreshold,
+                        Metric::Sources => AlertKind::SourceThreshold,
+                    },
+                    direction,
+                    source: None,
+                    port: rule.port,
+                    value: after,
+                    threshold: rule.threshold,
+                    window: rule.window,
+                    rule: Some(rule.to_string()),
+                    rule_index: Some(index),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use libc::timeval;
+
+    use crate::alert::{AlertKind, Detector};
+    use crate::capture::{Direction, ExtractedHeaders};
+    use crate::hooks::Action;
+    use crate::rules::{Metric, Rule, RuleDetector};
+    use crate::sensitive::IpAddress;
+
+    fn headers(secs: i64, source: u8, dest_port: u16) -> ExtractedHeaders {
+        ExtractedHeaders {
+            source_ip: IpAddress::V4([192, 0, 2, source]),
+            source_port: 40000,
+            dest_ip: IpAddress::V4([10, 0, 0, 1]),
+            dest_port,
+            capture_ts: timeval {
+                tv_sec: secs,
+                tv_usec: 0,
+            },
+            syn: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let rule: Rule =
+            "port=22 sources>100 per 60s webhook=http://127.0.0.1:9000/hook exec=logger -t pulso"
+                .parse()
+                .unwrap();
+        assert_eq!(rule.port, Some(22));
+        assert_eq!(rule.metric, Metric::Sources);
+        assert_eq!(rule.threshold, 100);
+        assert_eq!(rule.window, Duration::from_secs(60));
+        assert_eq!(
+            rule.actions[1],
+            Action::Exec(vec!["logger".into(), "-t".into(), "pulso".into()])
+        );
+        assert_eq!(rule.to_string(), "port=22 sources>100 per 60s");
+
+        assert!("port=22 sources>100".parse::<Rule>().is_err());
+        assert!("bytes>100 per 1s".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_exec_must_be_last() {
+        let rule = "connections>1 per 1s exec=logger webhook=http://127.0.0.1:9000/hook";
+        assert!(rule.parse::<Rule>().is_err());
+        assert!("connections>1 per 1s exec=logger exec=true"
+            .parse::<Rule>()
+            .is_err());
+
+        let rule: Rule = "connections>1 per 1s exec=logger -t pulso=1"
+            .parse()
+            .unwrap();
+        assert_eq!(rule.actions.len(), 1);
+    }
+
+    #[test]
+    fn test_sources_threshold() {
+        let rule: Rule = "port=22 sources>2 per 10s".parse().unwrap();
+        let mut detector = RuleDetector::new(vec![rule]);
+        let mut alerts = Vec::new();
+
+        for (secs, source, port) in [(0, 1, 22), (1, 1, 22), (2, 2, 22), (3, 3, 80)] {
+            detector.observe(Direction::In, &headers(secs, source, port), &mut alerts);
+        }
+        assert!(alerts.is_empty());
+
+        detector.observe(Direction::In, &headers(4, 3, 22), &mut alerts);
+        detector.observe(Direction::In, &headers(5, 4, 22), &mut alerts);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::SourceThreshold);
+        assert_eq!(alerts[0].value, 3);
+        assert_eq!(alerts[0].rule.as_deref(), Some("port=22 sources>2 per 10s"));
+
+        // sources 1 and 2 expire, then the threshold is crossed again
+        detector.observe(Direction::In, &headers(12, 5, 22), &mut alerts);
+        detector.observe(Direction::In, &headers(13, 6, 22), &mut alerts);
+        assert_eq!(alerts.len(), 2);
+    }
+}
@@ -1,19 +1,61 @@
 use anyhow::{Context, Result};
 use futures::stream::StreamExt;
 use log::{debug, error, info, warn};
+use pcap::{Active, PacketStream};
 use tokio::runtime::{self, Runtime as TokioRuntime};
-use tokio::time::{self, Duration};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Duration, Instant};
 
-use crate::capture::{capture_from_device, Codec};
-use crate::collector::Collector;
+use crate::capture::{capture_from_device, is_loopback, Codec, Direction, Handshake};
+use crate::checkpoint::Checkpoint;
+use crate::collector::{unix_now, Collector};
+use crate::control::ControlSocket;
+use crate::output::Outputs;
 
+/// why a capture stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// a limit was reached or the capture closed
+    Finished,
+    /// SIGINT or SIGTERM was received
+    Interrupted,
+}
+
+/// captures until a limit is reached or the process is interrupted, writing a digest at each
+/// interval. with a checkpoint, the collector state is also saved at its interval and after
+/// each digest. with a control socket, its requests are answered from the collector as they arrive
 pub fn collect_async(
     device_name: &str,
     connection_limit: Option<u64>,
     time_limit: Option<u64>,
     collector: &mut Collector,
-) -> Result<()> {
-    let capture = capture_from_device(device_name)?.setnonblock()?;
+    outputs: &mut Outputs,
+    checkpoint: Option<&Checkpoint>,
+    control: Option<&ControlSocket>,
+) -> Result<Stopped> {
+    let mut directions = collector.directions().list().to_vec();
+    if directions.len() == 2 && is_loopback(device_name)? {
+        // a connection to this host would be counted once leaving and once arriving
+        info!(
+            "counting connections on loopback device {} as inbound only",
+            device_name
+        );
+        directions.retain(|&direction| direction == Direction::In);
+    }
+    let mut segments: Vec<(Direction, Handshake)> = directions
+        .into_iter()
+        .map(|direction| (direction, Handshake::Syn))
+        .collect();
+    if collector.wants_answers() {
+        segments.push((Direction::Out, Handshake::SynAck));
+    }
+    let captures = segments
+        .into_iter()
+        .map(|(direction, handshake)| {
+            let capture = capture_from_device(device_name, direction, handshake)?.setnonblock()?;
+            Ok((direction, handshake, capture))
+        })
+        .collect::<Result<Vec<_>>>()?;
     let timeout_duration = Duration::from_secs(time_limit.unwrap_or(u64::MAX));
 
     debug!("starting tokio runtime");
@@ -24,42 +66,168 @@ pub fn collect_async(
         .context("build tokio runtime")?;
 
     runtime.block_on(async {
-        let mut stream = capture
-            .stream(Codec)
-            .context("capture from interface as stream")?;
-
+        let mut streams = captures
+            .into_iter()
+            .map(|(direction, handshake, capture)| {
+                let stream = capture
+                    .stream(Codec {
+                        direction,
+                        handshake,
+                    })
+                    .context("capture from interface as stream")?;
+                Ok((direction, handshake, stream))
+            })
+            .collect::<Result<Vec<_>>>()?;
         let timeout_future = time::timeout(timeout_duration, futures::future::pending::<()>());
         tokio::pin!(timeout_future);
+        let mut ticker = collector
+            .digest_interval()
+            .map(|interval| time::interval_at(Instant::now() + interval, interval));
+        let mut saver = checkpoint
+            .map(|c| time::interval_at(Instant::now() + c.save_interval(), c.save_interval()));
+        let mut interrupt = signal(SignalKind::interrupt()).context("handle SIGINT")?;
+        let mut terminate = signal(SignalKind::terminate()).context("handle SIGTERM")?;
+        let mut queries = control
+            .map(|c| c.serve().context("serve control socket"))
+            .transpose()?;
+        let mut last_dropped = vec![0; streams.len()];
+        let mut stopped = Stopped::Finished;
 
         info!("starting capture on device: {}", device_name);
 
-        loop {
-            tokio::select! {
-                next = stream.next() => match next {
-                   Some(Ok(packet)) => match (collector.process(packet), connection_limit) {
-                        (Ok(count), Some(limit)) if count > limit - 1 => {
-                            info!("connection limit reached. exiting");
-                            break;
+        'capture: loop {
+            // the merged stream borrows the captures, which are needed again for their stats
+            let mut stream = futures::stream::select_all(streams.iter_mut().map(|(_, _, s)| s));
+            loop {
+                let tick = async {
+                    match &mut ticker {
+                        Some(ticker) => ticker.tick().await,
+                        None => futures::future::pending().await,
+                    }
+                };
+                let save = async {
+                    match &mut saver {
+                        Some(saver) => saver.tick().await,
+                        None => futures::future::pending().await,
+                    }
+                };
+                let query = async {
+                    match &mut queries {
+                        Some(queries) => queries.recv().await,
+                        None => futures::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    next = stream.next() => match next {
+                        Some(Ok(packet)) => {
+                            let result = collector.process(packet);
+                            for alert in collector.take_alerts() {
+                                if let Err(e) = outputs.emit(&alert) {
+                                    warn!("alert output error: {:#}", e);
+                                }
+                            }
+                            match (result, connection_limit) {
+                                (Ok(count), Some(limit)) if count > limit - 1 => {
+                                    info!("connection limit reached. exiting");
+                                    break 'capture;
+                                }
+                                (Err(e), _) => warn!("processing error: {:#}", e),
+                                _ => (),
+                            }
+                        }
+                        Some(Err(pcap_error)) => error!("capture error: {:?}", pcap_error),
+                        None => {
+                            warn!("capture stream closed. exiting");
+                            break 'capture;
                         }
-                        (Err(e), _) => warn!("processing error: {:#}", e),
-                        _ => (),
                     },
-                    Some(Err(pcap_error)) => error!("capture error: {:?}", pcap_error),
-                    None => {
-                        warn!("capture stream closed. exiting");
-                        break;
+                    _ = &mut timeout_future => {
+                        info!("time limit reached. exiting");
+                        break 'capture;
+                    }
+                    _ = tick => break,
+                    _ = save => save_checkpoint(checkpoint, collector),
+                    Some(query) = query => query.answer(collector, unix_now()),
+                    _ = interrupt.recv() => {
+                        info!("interrupted. exiting");
+                        stopped = Stopped::Interrupted;
+                        break 'capture;
+                    }
+                    _ = terminate.recv() => {
+                        info!("terminated. exiting");
+                        stopped = Stopped::Interrupted;
+                        break 'capture;
                     }
-                },
-                _ = &mut timeout_future => {
-                    info!("time limit reached. exiting");
-                    break;
                 }
-                // TODO break on SIGINT
             }
+
+            drop(stream);
+            record_dropped(&mut streams, &mut last_dropped, collector);
+            if let Err(e) = outputs.flush(collector) {
+                warn!("digest output error: {:#}", e);
+            }
+            save_checkpoint(checkpoint, collector);
         }
 
-        info!("pcap stats {:?}", stream.capture_mut().stats());
+        record_dropped(&mut streams, &mut last_dropped, collector);
+        for (direction, handshake, stream) in streams.iter_mut() {
+            info!(
+                "pcap stats {direction} {handshake:?} {:?}",
+                stream.capture_mut().stats()
+            );
+        }
 
-        Ok(())
+        Ok(stopped)
     })
 }
+
+/// calls `tick` at each interval, until SIGINT or SIGTERM is received
+pub fn run_periodic<F: FnMut()>(interval: Duration, mut tick: F) -> Result<()> {
+    let runtime: TokioRuntime = runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .context("build tokio runtime")?;
+
+    runtime.block_on(async {
+        let mut ticker = time::interval_at(Instant::now() + interval, interval);
+        let mut interrupt = signal(SignalKind::interrupt()).context("handle SIGINT")?;
+        let mut terminate = signal(SignalKind::terminate()).context("handle SIGTERM")?;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => tick(),
+                _ = interrupt.recv() => {
+                    info!("interrupted. exiting");
+                    return Ok(());
+                }
+                _ = terminate.recv() => {
+                    info!("terminated. exiting");
+                    return Ok(());
+                }
+            }
+        }
+    })
+}
+
+fn save_checkpoint(checkpoint: Option<&Checkpoint>, collector: &Collector) {
+    if let Some(checkpoint) = checkpoint {
+        if let Err(e) = checkpoint.save(collector) {
+            warn!("checkpoint error: {:#}", e);
+        }
+    }
+}
+
+/// adds the packets dropped by each capture since the last call to the collector's window
+fn record_dropped(
+    streams: &mut [(Direction, Handshake, PacketStream<Active, Codec>)],
+    last_dropped: &mut [u64],
+    collector: &mut Collector,
+) {
+    for ((_, _, stream), last) in streams.iter_mut().zip(last_dropped.iter_mut()) {
+        if let Ok(stats) = stream.capture_mut().stats() {
+            let dropped = stats.dropped as u64 + stats.if_dropped as u64;
+            collector.record_dropped(dropped.saturating_sub(*last));
+            *last = dropped;
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::aggregate::Table;
+use crate::capture::ExtractedHeaders;
+use crate::collector::Mergeable;
+use crate::sensitive::IpAddress;
+
+/// IP identification used by every probe sent by zmap
+const ZMAP_IP_ID: u16 = 54321;
+
+/// origin of a SYN, judged by traits of well known internet-wide scanners
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Classification {
+    Organic,
+    /// fixed IP identification
+    Zmap,
+    /// IP identification derived from the target address, target port and sequence number
+    Masscan,
+    /// real TCP stacks always advertise at least a maximum segment size
+    NoOptions,
+}
+
+impl Classification {
+    pub const ALL: [Classification; 4] = [
+        Classification::Organic,
+        Classification::Zmap,
+        Classification::Masscan,
+        Classification::NoOptions,
+    ];
+
+    pub fn of(headers: &ExtractedHeaders) -> Self {
+        let syn = &headers.syn;
+        match (syn.ip_id, headers.dest_ip) {
+            (Some(ZMAP_IP_ID), _) => return Classification::Zmap,
+            (Some(ip_id), IpAddress::V4(dest)) => {
+                let masscan_id =
+                    u32::from_be_bytes(dest) ^ headers.dest_port as u32 ^ syn.sequence_number;
+                if ip_id == masscan_id as u16 {
+                    return Classification::Masscan;
+                }
+            }
+            _ => (),
+        }
+        if syn.options.is_empty() {
+            Classification::NoOptions
+        } else {
+            Classification::Organic
+        }
+    }
+
+    pub fn is_scanner(&self) -> bool {
+        *self != Classification::Organic
+    }
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Classification::Organic => "organic",
+            Classification::Zmap => "zmap",
+            Classification::Masscan => "masscan",
+            Classification::NoOptions => "no-options",
+        })
+    }
+}
+
+/// counts scanner and organic connections per destination port
+#[derive(Debug, Default)]
+pub struct ScannerCounter {
+    pub(crate) counts: HashMap<(u16, Classification), u64>,
+}
+
+impl Mergeable for ScannerCounter {
+    fn merge(&mut self, other: Self) {
+        self.counts.merge(other.counts);
+    }
+}
+
+impl ScannerCounter {
+    pub fn process(&mut self, headers: &ExtractedHeaders) {
+        *self
+            .counts
+            .entry((headers.dest_port, Classification::of(headers)))
+            .or_default() += 1;
+    }
+
+    /// one row per port, with a count for organic connections and for each kind of scanner
+    pub fn table(&self) -> Table {
+        let mut grouped: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        for ((port, classification), count) in &self.counts {
+            *grouped
+                .entry(port.to_string())
+                .or_default()
+                .entry(classification.to_string())
+                .or_default() += count;
+        }
+        Table::from_grouped(grouped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libc::timeval;
+
+    use crate::capture::{ExtractedHeaders, SynHeaders};
+    use crate::scanner::Classification;
+    use crate::sensitive::IpAddress;
+
+    fn headers(dest_ip: IpAddress, dest_port: u16, syn: SynHeaders) -> ExtractedHeaders {
+        ExtractedHeaders {
+            source_ip: IpAddress::V4([192, 0, 2, 1]),
+            source_port: 40000,
+            dest_ip,
+            dest_port,
+            capture_ts: timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            syn,
+        }
+    }
+
+    const MSS_OPTION: [u8; 4] = [2, 4, 5, 0xb4];
+
+    #[test]
+    fn test_zmap() {
+        let syn = SynHeaders {
+            ip_id: Some(54321),
+            ..Default::default()
+        };
+        let h = headers(IpAddress::V4([10, 0, 0, 1]), 443, syn);
+        assert_eq!(Classification::of(&h), Classification::Zmap);
+    }
+
+    #[test]
+    fn test_masscan() {
+        let dest = [10, 0, 0, 1];
+        let sequence_number = 0x1234_5678;
+        let ip_id = (u32::from_be_bytes(dest) ^ 22 ^ sequence_number) as u16;
+        let syn = SynHeaders {
+            ip_id: Some(ip_id),
+            sequence_number,
+            options: MSS_OPTION.to_vec(),
+            ..Default::default()
+        };
+        let h = headers(IpAddress::V4(dest), 22, syn);
+        assert_eq!(Classification::of(&h), Classification::Masscan);
+    }
+
+    #[test]
+    fn test_organic() {
+        let syn = SynHeaders {
+            ip_id: Some(1),
+            sequence_number: 0x1234_5678,
+            options: MSS_OPTION.to_vec(),
+            ..Default::default()
+        };
+        let h = headers(IpAddress::V6([0; 16]), 22, syn.clone());
+        assert_eq!(Classification::of(&h), Classification::Organic);
+
+        let no_options = SynHeaders {
+            options: vec![],
+            ..syn
+        };
+        let h = headers(IpAddress::V6([0; 16]), 22, no_options);
+        assert_eq!(Classification::of(&h), Classification::NoOptions);
+        assert!(Classification::of(&h).is_scanner());
+    }
+}
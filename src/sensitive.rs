@@ -1,11 +1,32 @@
 use std::fmt;
+use std::net::IpAddr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum IpAddress {
     V6([u8; 16]),
     V4([u8; 4]),
 }
 
+impl IpAddress {
+    /// the real address, regardless of the "privacy" feature.
+    /// only for outputs which are useless without it, such as firewall blocklists
+    pub fn reveal(&self) -> IpAddr {
+        match *self {
+            IpAddress::V6(bytes) => IpAddr::from(bytes),
+            IpAddress::V4(bytes) => IpAddr::from(bytes),
+        }
+    }
+}
+
+impl From<IpAddr> for IpAddress {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V6(ip) => IpAddress::V6(ip.octets()),
+            IpAddr::V4(ip) => IpAddress::V4(ip.octets()),
+        }
+    }
+}
+
 impl fmt::Display for IpAddress {
     /// produces a 16 character hex string if "privacy" feature is enabled (default)
     /// otherwise, produces a formatted address
@@ -31,12 +52,7 @@ impl fmt::Display for IpAddress {
         }
         #[cfg(not(feature = "privacy"))]
         {
-            use std::net::IpAddr;
-            let addr = match *self {
-                IpAddress::V6(bytes) => IpAddr::from(bytes),
-                IpAddress::V4(bytes) => IpAddr::from(bytes),
-            };
-            addr.fmt(f)
+            self.reveal().fmt(f)
         }
     }
 }
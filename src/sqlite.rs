@@ -0,0 +1,189 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+
+use crate::aggregate::Digest;
+use crate::output::Sink;
+
+/// one row per window, and one row per connection key counted in it.
+/// times are in unix seconds, so that `datetime(start, 'unixepoch')` reads them
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS windows (
+    id INTEGER PRIMARY KEY,
+    start INTEGER NOT NULL,
+    end INTEGER NOT NULL,
+    device TEXT NOT NULL,
+    connections INTEGER NOT NULL,
+    sources INTEGER NOT NULL,
+    dropped INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS connections (
+    window INTEGER NOT NULL REFERENCES windows(id) ON DELETE CASCADE,
+    direction TEXT NOT NULL,
+    source TEXT,
+    destination TEXT,
+    port INTEGER,
+    count INTEGER NOT NULL,
+    first INTEGER NOT NULL,
+    last INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS windows_start ON windows(start);
+CREATE INDEX IF NOT EXISTS windows_end ON windows(end);
+CREATE INDEX IF NOT EXISTS connections_window ON connections(window);
+CREATE INDEX IF NOT EXISTS connections_port ON connections(port, window);
+CREATE INDEX IF NOT EXISTS connections_source ON connections(source, window);
+";
+
+/// digest windows stored in a local SQLite database, pruned to a retention period.
+/// sources are stored as displayed, so they are pseudonyms with the "privacy" feature
+pub struct Sqlite {
+    db: Connection,
+    device: String,
+    retention: Option<Duration>,
+}
+
+impl Sqlite {
+    pub fn open(path: &Path, device: &str, retention: Option<Duration>) -> Result<Self> {
+        let db = Connection::open(path)
+            .with_context(|| format!("open sqlite database {}", path.display()))?;
+        db.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")?;
+        db.execute_batch(SCHEMA).context("create sqlite schema")?;
+        Ok(Sqlite {
+            db,
+            device: device.to_string(),
+            retention,
+        })
+    }
+
+    /// removes windows which ended before the retention period, and their connections
+    pub fn prune(&self, now: Duration) -> Result<usize> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let cutoff = now.saturating_sub(retention).as_secs();
+        let pruned = self
+            .db
+            .execute("DELETE FROM windows WHERE end < ?1", [cutoff])
+            .context("prune sqlite windows")?;
+        Ok(pruned)
+    }
+}
+
+impl Sink for Sqlite {
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let tx = self.db.transaction()?;
+        tx.execute(
+            "INSERT INTO windows (start, end, device, connections, sources, dropped)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                digest.start.as_secs(),
+                digest.end.as_secs(),
+                self.device,
+                digest.connections,
+                digest.sources,
+                digest.dropped
+            ],
+        )?;
+        let window = tx.last_insert_rowid();
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO connections
+                 (window, direction, source, destination, port, count, first, last)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for flow in &digest.flows {
+                let key = &flow.key;
+                insert.execute(params![
+                    window,
+                    key.direction.to_string(),
+                    key.source_ip.map(|ip| ip.to_string()),
+                    key.dest_ip.map(|ip| ip.to_string()),
+                    key.dest_port,
+                    flow.count,
+                    flow.first.as_secs(),
+                    flow.last.as_secs()
+                ])?;
+            }
+        }
+        tx.commit().context("write sqlite window")?;
+        self.prune(digest.end)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use crate::aggregate::Digest;
+    use crate::capture::Direction;
+    use crate::collector::{ConnectionKey, Flow};
+    use crate::output::Sink;
+    use crate::sensitive::IpAddress;
+    use crate::sqlite::Sqlite;
+
+    fn window(start: u64, port: u16, count: u64) -> Digest {
+        let source = IpAddress::V4([192, 0, 2, 1]);
+        Digest {
+            start: Duration::from_secs(start),
+            end: Duration::from_secs(start + 60),
+            connections: count,
+            sources: 1,
+            flows: vec![Flow {
+                key: ConnectionKey {
+                    direction: Direction::In,
+                    source_ip: Some(source),
+                    dest_ip: None,
+                    dest_port: Some(port),
+                },
+                count,
+                first: Duration::from_secs(start + 1),
+                last: Duration::from_secs(start + 59),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_store_and_prune() {
+        let path = std::env::temp_dir().join(format!("pulso-{}.sqlite", std::process::id()));
+        let day = Duration::from_secs(86400);
+        let mut sqlite = Sqlite::open(&path, "eth0", Some(day)).unwrap();
+
+        sqlite.digest(&window(1697700000, 5432, 4)).unwrap();
+        sqlite.digest(&window(1697700060, 22, 1)).unwrap();
+        let source = IpAddress::V4([192, 0, 2, 1]).to_string();
+        let count: u64 = sqlite
+            .db
+            .query_row(
+                "SELECT sum(count) FROM connections JOIN windows ON window = id
+                 WHERE port = 5432 AND source = ?1 AND device = 'eth0'",
+                [&source],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 4);
+
+        // a day later, both earlier windows are past the retention period
+        sqlite
+            .digest(&window(1697700000 + 86400 + 120, 22, 2))
+            .unwrap();
+        let (windows, connections): (u64, u64) = sqlite
+            .db
+            .query_row(
+                "SELECT (SELECT count(*) FROM windows), (SELECT count(*) FROM connections)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((windows, connections), (1, 1));
+
+        drop(sqlite);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::aggregate::Digest;
+use crate::alert::Alert;
+use crate::output::Sink;
+
+/// keeps datagrams within the usual ethernet MTU
+const MAX_PAYLOAD: usize = 1432;
+const PREFIX: &str = "pulso";
+
+/// counters for a StatsD agent over UDP, tagged in the DogStatsD dialect if enabled.
+/// without tags, the direction and port are part of the metric name.
+pub struct Statsd {
+    socket: UdpSocket,
+    tags: bool,
+}
+
+impl Statsd {
+    pub fn connect(addr: &str, tags: bool) -> Result<Self> {
+        let addr = addr
+            .to_socket_addrs()
+            .with_context(|| format!("resolve statsd {addr}"))?
+            .next()
+            .ok_or(anyhow!("no address for statsd {addr}"))?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).context("bind statsd socket")?;
+        socket
+            .connect(addr)
+            .with_context(|| format!("connect to statsd {addr}"))?;
+        Ok(Statsd { socket, tags })
+    }
+
+    fn metric(&self, name: &str, value: u64, kind: &str, tags: &[(&str, String)]) -> String {
+        if tags.is_empty() {
+            format!("{PREFIX}.{name}:{value}|{kind}")
+        } else if self.tags {
+            let tags: Vec<String> = tags.iter().map(|(k, v)| format!("{k}:{v}")).collect();
+            format!("{PREFIX}.{name}:{value}|{kind}|#{}", tags.join(","))
+        } else {
+            let parts: Vec<&str> = tags.iter().map(|(_, v)| v.as_str()).collect();
+            format!("{PREFIX}.{name}.{}:{value}|{kind}", parts.join("."))
+        }
+    }
+
+    /// packs metrics into as few datagrams as possible
+    fn send(&self, metrics: &[String]) -> Result<()> {
+        let mut payload = String::new();
+        for metric in metrics {
+            if !payload.is_empty() && payload.len() + 1 + metric.len() > MAX_PAYLOAD {
+                self.socket
+                    .send(payload.as_bytes())
+                    .context("send to statsd")?;
+                payload.clear();
+            }
+            if !payload.is_empty() {
+                payload.push('\n');
+            }
+            payload.push_str(metric);
+        }
+        if !payload.is_empty() {
+            self.socket
+                .send(payload.as_bytes())
+                .context("send to statsd")?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Statsd {
+    /// connections per direction and port, distinct sources and capture drops
+    fn digest(&mut self, digest: &Digest) -> Result<()> {
+        let mut metrics: Vec<String> = digest
+            .ports
+            .iter()
+            .map(|(direction, port, count)| {
+                let tags = [
+                    ("direction", direction.to_string()),
+                    ("port", port.to_string()),
+                ];
+                self.metric("connections", *count, "c", &tags)
+            })
+            .collect();
+        metrics.push(self.metric("sources", digest.sources, "g", &[]));
+        metrics.push(self.metric("pcap.dropped", digest.dropped, "c", &[]));
+        self.send(&metrics)
+    }
+
+    fn alert(&mut self, alert: &Alert) -> Result<()> {
+        let metric = self.metric("alerts", 1, "c", &[("kind", alert.kind.to_string())]);
+        self.send(&[metric])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use crate::aggregate::Digest;
+    use crate::capture::Direction;
+    use crate::output::Sink;
+    use crate::statsd::Statsd;
+
+    fn received(server: &UdpSocket) -> String {
+        let mut buf = [0u8; 2048];
+        let len = server.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    fn digest() -> Digest {
+        Digest {
+            ports: vec![(Direction::In, 22, 5), (Direction::In, 443, 2)],
+            sources: 3,
+            dropped: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dogstatsd_digest() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+
+        let mut statsd = Statsd::connect(&addr, true).unwrap();
+        statsd.digest(&digest()).unwrap();
+        assert_eq!(
+            received(&server),
+            "pulso.connections:5|c|#direction:inbound,port:22\n\
+             pulso.connections:2|c|#direction:inbound,port:443\n\
+             pulso.sources:3|g\n\
+             pulso.pcap.dropped:1|c"
+        );
+    }
+
+    #[test]
+    fn test_statsd_names_and_batching() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+
+        let mut statsd = Statsd::connect(&addr, false).unwrap();
+        let mut digest = digest();
+        digest.ports = (1..=100).map(|port| (Direction::In, port, 1)).collect();
+        statsd.digest(&digest).unwrap();
+
+        let datagrams: Vec<String> = (0..3).map(|_| received(&server)).collect();
+        assert!(datagrams[0].starts_with("pulso.connections.inbound.1:1|c\n"));
+        assert!(datagrams.iter().all(|d| d.len() <= 1432));
+        assert!(datagrams[2].ends_with("pulso.sources:3|g\npulso.pcap.dropped:1|c"));
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+/// formats a unix timestamp as an RFC 3339 UTC date and time, with microseconds
+pub fn rfc3339(ts: Duration) -> String {
+    let secs = ts.as_secs();
+    // days to civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:06}Z",
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        ts.subsec_micros()
+    )
+}
+
+/// reads a UTC date and time as written by [rfc3339], with an optional fraction of a second
+pub fn parse_rfc3339(s: &str) -> Result<Duration> {
+    let invalid = || anyhow!("invalid timestamp: {s}");
+    let (date, time) = s
+        .strip_suffix('Z')
+        .and_then(|s| s.split_once('T'))
+        .ok_or_else(invalid)?;
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let numbers =
+        |s: &str, sep: char| -> Option<Vec<i64>> { s.split(sep).map(|n| n.parse().ok()).collect() };
+    let (Some([year, month, day]), Some([hour, minute, second])) = (
+        numbers(date, '-').and_then(|n| <[i64; 3]>::try_from(n).ok()),
+        numbers(time, ':').and_then(|n| <[i64; 3]>::try_from(n).ok()),
+    ) else {
+        return Err(invalid());
+    };
+    let in_range = (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && (0..24).contains(&hour)
+        && (0..60).contains(&minute)
+        && (0..60).contains(&second);
+    if !in_range || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    // civil date to days, from the same source as rfc3339
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    let nanos = format!("{fraction:0<9}")[..9].parse().unwrap_or(0);
+    Ok(Duration::new(
+        u64::try_from(secs).map_err(|_| invalid())?,
+        nanos,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::time::{parse_rfc3339, rfc3339};
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(
+            rfc3339(Duration::new(1697723025, 2_500_000)),
+            "2023-10-19T13:43:45.002500Z"
+        );
+        assert_eq!(rfc3339(Duration::ZERO), "1970-01-01T00:00:00.000000Z");
+        assert_eq!(
+            rfc3339(Duration::from_secs(951782400)),
+            "2000-02-29T00:00:00.000000Z"
+        );
+
+        for ts in [
+            Duration::new(1697723025, 2_500_000),
+            Duration::from_secs(951782400),
+        ] {
+            assert_eq!(parse_rfc3339(&rfc3339(ts)).unwrap(), ts);
+        }
+        assert_eq!(
+            parse_rfc3339("2023-10-19T13:43:45Z").unwrap(),
+            Duration::from_secs(1697723025)
+        );
+        assert!(parse_rfc3339("2023-10-19 13:43:45").is_err());
+        assert!(parse_rfc3339("2023-13-19T13:43:45Z").is_err());
+    }
+}
@@ -77,6 +77,6 @@ impl CliProcess {
     }
 
     pub fn output_lines(&self) -> Vec<&str> {
-        self.output_buffer.lines().into_iter().collect()
+        self.output_buffer.lines().collect()
     }
 }
@@ -14,7 +14,7 @@ fn test_help() {
     Scenario::default()
         .start("--help")
         .check_result(Some(0), |o| {
-            assert!(o.iter().any(|line| line.contains(&"Usage:")));
+            assert!(o.iter().any(|line| line.contains("Usage:")));
         });
 }
 
@@ -33,6 +33,13 @@ fn test_invalid_device() {
         .check_result(Some(1), |o| assert!(o.is_empty()));
 }
 
+#[test]
+fn test_invalid_group_by() {
+    Scenario::default()
+        .start("--device lo --key src --group-by dport")
+        .check_result(Some(2), |o| assert!(o.is_empty()));
+}
+
 #[test]
 fn test_connection_limit_ipv6() {
     Scenario::default()